@@ -0,0 +1,283 @@
+//! Link and image analysis of html mail, for checking what a message points to
+
+use serde::Serialize;
+
+use crate::{
+    html::{self, Token},
+    Message, MessageSource,
+};
+
+/// words marking a link as an unsubscribe one, in its url or its text
+const UNSUBSCRIBE_KEYWORDS: [&str; 3] = ["unsubscribe", "opt-out", "opt out"];
+
+/// What an html body links to and loads
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct HtmlReport {
+    /// every `<a href>`, in document order
+    pub links: Vec<LinkInfo>,
+    /// every `<img src>`, in document order
+    pub images: Vec<ImageInfo>,
+    /// unsubscribe targets found in the body or in a `List-Unsubscribe` header
+    pub unsubscribe: Vec<Unsubscribe>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LinkInfo {
+    pub href: String,
+    /// the anchor text, with image alt texts included and whitespace collapsed
+    pub text: String,
+    /// the link uses plain `http://`
+    pub insecure: bool,
+    pub unsubscribe: bool,
+    /// the text shows a url or domain on another host than the one the link goes to, like
+    /// `https://bank.example` linking to `https://phish.example`
+    pub mismatched: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ImageInfo {
+    pub src: String,
+    pub alt: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// the image is 1x1 (or smaller) or hidden, so it's only there to track opens
+    pub tracking_pixel: bool,
+    /// the image is loaded over plain `http://`
+    pub insecure: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Unsubscribe {
+    /// an http(s) url or a `mailto:` address
+    pub target: String,
+    pub source: UnsubscribeSource,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum UnsubscribeSource {
+    /// a link in the html body
+    Body,
+    /// the `List-Unsubscribe` header
+    Header,
+}
+
+impl HtmlReport {
+    /// links using plain `http://`
+    pub fn insecure_links(&self) -> impl Iterator<Item = &LinkInfo> {
+        self.links.iter().filter(|link| link.insecure)
+    }
+
+    pub fn tracking_pixels(&self) -> impl Iterator<Item = &ImageInfo> {
+        self.images.iter().filter(|image| image.tracking_pixel)
+    }
+
+    /// links whose text shows another host than the one they go to
+    pub fn mismatched_links(&self) -> impl Iterator<Item = &LinkInfo> {
+        self.links.iter().filter(|link| link.mismatched)
+    }
+
+    /// adds the targets of a `List-Unsubscribe` header, like `<mailto:u@example.com>, <https://example.com/u>`
+    pub fn add_list_unsubscribe(&mut self, header: &str) {
+        for target in header.split(',') {
+            let target = target.trim().trim_start_matches('<').trim_end_matches('>').trim();
+
+            if target.is_empty() || self.unsubscribe.iter().any(|unsub| unsub.target == target) {
+                continue;
+            }
+
+            self.unsubscribe.push(Unsubscribe { target: target.to_string(), source: UnsubscribeSource::Header });
+        }
+    }
+}
+
+impl Message {
+    /// analyzes the links and images of the html body, the report is empty without one
+    pub fn analyze_html(&self) -> HtmlReport {
+        self.html_body.as_deref().map(analyze_html).unwrap_or_default()
+    }
+
+    /// like [`Message::analyze_html`], also picking up the `List-Unsubscribe` header of the message's source
+    pub fn analyze_html_with_source(&self, source: &MessageSource) -> HtmlReport {
+        let mut report = self.analyze_html();
+
+        for header in source.headers().get_all("List-Unsubscribe") {
+            report.add_list_unsubscribe(header);
+        }
+
+        report
+    }
+}
+
+/// analyzes the links and images of an html document
+pub fn analyze_html(body: &str) -> HtmlReport {
+    let mut report = HtmlReport::default();
+
+    for anchor in html::anchors(body) {
+        let unsubscribe = mentions_unsubscribe(&anchor.href) || mentions_unsubscribe(&anchor.text);
+
+        if unsubscribe && !report.unsubscribe.iter().any(|unsub| unsub.target == anchor.href) {
+            report.unsubscribe.push(Unsubscribe { target: anchor.href.clone(), source: UnsubscribeSource::Body });
+        }
+
+        report.links.push(LinkInfo {
+            insecure: is_insecure(&anchor.href),
+            unsubscribe,
+            mismatched: is_mismatched(&anchor.href, &anchor.text),
+            href: anchor.href,
+            text: anchor.text,
+        });
+    }
+
+    for token in html::tokenize(body) {
+        if !matches!(&token, Token::Start { name, .. } if name == "img") {
+            continue;
+        }
+
+        let Some(src) = token.attr("src").map(str::trim) else {
+            continue;
+        };
+
+        let style = token.attr("style").unwrap_or_default().to_ascii_lowercase().replace(' ', "");
+        let width = token.attr("width").and_then(pixels).or_else(|| style_pixels(&style, "width"));
+        let height = token.attr("height").and_then(pixels).or_else(|| style_pixels(&style, "height"));
+        let hidden = style.contains("display:none") || style.contains("visibility:hidden");
+
+        report.images.push(ImageInfo {
+            src: src.to_string(),
+            alt: token.attr("alt").map(str::to_string),
+            tracking_pixel: hidden || matches!((width, height), (Some(w), Some(h)) if w <= 1 && h <= 1),
+            insecure: is_insecure(src),
+            width,
+            height,
+        });
+    }
+
+    report
+}
+
+fn mentions_unsubscribe(text: &str) -> bool {
+    let text = text.to_lowercase();
+    UNSUBSCRIBE_KEYWORDS.iter().any(|keyword| text.contains(keyword))
+}
+
+fn is_insecure(url: &str) -> bool {
+    url.get(..7).is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://"))
+}
+
+/// whether the text is a url or domain whose host isn't the host of `href`, `www.` aside
+fn is_mismatched(href: &str, text: &str) -> bool {
+    let Some(shown) = shown_host(text) else {
+        return false;
+    };
+
+    let actual = reqwest::Url::parse(href).ok().and_then(|url| url.host_str().map(str::to_ascii_lowercase));
+
+    actual.is_some_and(|actual| actual.trim_start_matches("www.") != shown.trim_start_matches("www."))
+}
+
+/// the host of a text that looks like a url or a bare domain, like `example.com/login`
+fn shown_host(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let lower = text.to_ascii_lowercase();
+    let rest = lower.strip_prefix("https://").or_else(|| lower.strip_prefix("http://")).unwrap_or(&lower);
+    let host = rest.split(['/', '?', '#', ':']).next().unwrap_or_default();
+
+    let labels: Vec<&str> = host.split('.').collect();
+    let is_domain = labels.len() > 1
+        && labels.iter().all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        && labels.last().is_some_and(|tld| tld.chars().all(|c| c.is_ascii_alphabetic()));
+
+    is_domain.then(|| host.to_string())
+}
+
+/// parses sizes like `1`, `1px` or `1.0`
+fn pixels(value: &str) -> Option<u32> {
+    let value = value.trim().trim_end_matches("px");
+    value.parse::<f32>().ok().map(|px| px.max(0.0).round() as u32)
+}
+
+/// finds `prop:<size>` in a whitespace free, lowercase inline style
+fn style_pixels(style: &str, prop: &str) -> Option<u32> {
+    style
+        .split(';')
+        .filter_map(|decl| decl.split_once(':'))
+        .find(|(name, _)| *name == prop)
+        .and_then(|(_, value)| pixels(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyze_html, UnsubscribeSource};
+
+    #[test]
+    fn finds_tracking_pixels() {
+        let report = analyze_html(
+            r#"<img src="https://t.example/open.gif" width="1" height="1">
+            <img src="https://t.example/o.gif" style="width: 1px; height: 0px">
+            <img src="https://cdn.example/hidden.png" width="600" style="display: none">
+            <img src="https://cdn.example/gone.png" style="visibility:hidden">
+            <img src="https://cdn.example/logo.png" width="1" height="80" alt="Logo">
+            <img src="https://cdn.example/banner.png">
+            <img alt="no src">"#,
+        );
+
+        assert_eq!(report.images.len(), 6);
+        let pixels: Vec<&str> = report.tracking_pixels().map(|image| image.src.as_str()).collect();
+        assert_eq!(
+            pixels,
+            ["https://t.example/open.gif", "https://t.example/o.gif", "https://cdn.example/hidden.png", "https://cdn.example/gone.png"]
+        );
+        assert_eq!((report.images[1].width, report.images[1].height), (Some(1), Some(0)));
+        assert_eq!(report.images[4].alt.as_deref(), Some("Logo"));
+    }
+
+    #[test]
+    fn flags_insecure_links_and_images() {
+        let report = analyze_html(
+            r#"<a href="HTTP://example.com/a">plain</a> <a href="https://example.com/b">tls</a>
+            <a href="mailto:help@example.com">mail</a> <img src="http://cdn.example/x.png">"#,
+        );
+
+        let insecure: Vec<&str> = report.insecure_links().map(|link| link.href.as_str()).collect();
+        assert_eq!(insecure, ["HTTP://example.com/a"]);
+        assert!(report.images[0].insecure);
+    }
+
+    #[test]
+    fn flags_text_showing_another_host() {
+        let report = analyze_html(
+            r#"<a href="https://phish.example/login">https://bank.example/login</a>
+            <a href="https://phish.example">bank.example</a>
+            <a href="https://www.bank.example/login">bank.example</a>
+            <a href="https://bank.example/account">https://bank.example</a>
+            <a href="https://phish.example">Log in to your bank</a>
+            <a href="https://phish.example">v1.2</a>"#,
+        );
+
+        let mismatched: Vec<&str> = report.mismatched_links().map(|link| link.text.as_str()).collect();
+        assert_eq!(mismatched, ["https://bank.example/login", "bank.example"]);
+    }
+
+    #[test]
+    fn collects_unsubscribe_targets() {
+        let mut report = analyze_html(
+            r#"<a href="https://example.com/u?id=1">Unsubscribe</a> <a href="https://example.com/prefs">Opt out</a>"#,
+        );
+        report.add_list_unsubscribe("<mailto:u@example.com>, <https://example.com/u?id=1>");
+
+        let targets: Vec<(&str, UnsubscribeSource)> =
+            report.unsubscribe.iter().map(|unsub| (unsub.target.as_str(), unsub.source)).collect();
+        assert_eq!(
+            targets,
+            [
+                ("https://example.com/u?id=1", UnsubscribeSource::Body),
+                ("https://example.com/prefs", UnsubscribeSource::Body),
+                ("mailto:u@example.com", UnsubscribeSource::Header),
+            ]
+        );
+    }
+}
@@ -0,0 +1,805 @@
+//! Sender authentication results (SPF, DKIM, DMARC) of received mail
+//!
+//! the results reported by the receiving server are read from the `Authentication-Results` and
+//! `Received-SPF` headers. with the `dkim` feature, DKIM signatures can also be checked offline
+//! against keys from a `TxtResolver`
+
+use serde::Serialize;
+
+use crate::{
+    mime::{split_header, Headers},
+    MessageSource,
+};
+
+#[cfg(feature = "dkim")]
+pub use dkim::{verify_dkim, DkimVerification, TxtResolver};
+
+/// The outcome of one authentication method
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum AuthResult {
+    Pass,
+    Fail,
+    SoftFail,
+    Neutral,
+    None,
+    TempError,
+    PermError,
+    /// anything else, like `policy` or `bestguesspass`, lowercase
+    Other(String),
+}
+
+/// One `method=result` entry, like `dkim=pass header.d=example.com`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MethodResult {
+    /// lowercase, without its version, like `spf` or `dkim`
+    pub method: String,
+    pub result: AuthResult,
+    /// the domain the result is about: `header.d` for dkim, the `smtp.mailfrom` domain for spf
+    /// and `header.from` for dmarc
+    pub domain: Option<String>,
+    pub reason: Option<String>,
+    /// the `ptype.property=value` pairs, like `("header.d", "example.com")`
+    pub properties: Vec<(String, String)>,
+}
+
+/// What the receiving servers reported about a message's authentication
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct AuthResults {
+    /// the server that did the checks, from the topmost `Authentication-Results` header
+    pub authserv_id: Option<String>,
+    /// every result in header order, `Authentication-Results` ones before `Received-SPF` ones
+    pub methods: Vec<MethodResult>,
+}
+
+impl AuthResult {
+    pub fn is_pass(&self) -> bool {
+        *self == AuthResult::Pass
+    }
+}
+
+impl From<&str> for AuthResult {
+    fn from(result: &str) -> Self {
+        match result.trim().to_ascii_lowercase().as_str() {
+            "pass" => AuthResult::Pass,
+            "fail" | "hardfail" => AuthResult::Fail,
+            "softfail" => AuthResult::SoftFail,
+            "neutral" => AuthResult::Neutral,
+            "none" => AuthResult::None,
+            "temperror" => AuthResult::TempError,
+            "permerror" => AuthResult::PermError,
+            other => AuthResult::Other(other.to_string()),
+        }
+    }
+}
+
+impl MethodResult {
+    /// the value of a property, like `header.d`, names are case insensitive
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(prop, _)| prop.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl AuthResults {
+    /// reads the results from the raw source of a message, only its headers are looked at
+    pub fn parse(raw: &[u8]) -> Self {
+        let (header_end, _) = split_header(raw);
+        Self::from_headers(&Headers::parse(&raw[..header_end]))
+    }
+
+    pub fn from_headers(headers: &Headers) -> Self {
+        let mut results = Self::default();
+
+        for header in headers.get_all("Authentication-Results") {
+            let (authserv_id, methods) = parse_authentication_results(header);
+
+            if results.authserv_id.is_none() {
+                results.authserv_id = authserv_id;
+            }
+            results.methods.extend(methods);
+        }
+
+        results
+            .methods
+            .extend(headers.get_all("Received-SPF").filter_map(parse_received_spf));
+
+        results
+    }
+
+    /// every result of a method, like all the `dkim` ones of a message signed twice
+    pub fn method<'a>(&'a self, method: &'a str) -> impl Iterator<Item = &'a MethodResult> + 'a {
+        self.methods
+            .iter()
+            .filter(move |result| result.method.eq_ignore_ascii_case(method))
+    }
+
+    pub fn spf(&self) -> Option<&MethodResult> {
+        self.method("spf").next()
+    }
+
+    /// the first passing dkim result, or else the first one
+    pub fn dkim(&self) -> Option<&MethodResult> {
+        self.method("dkim")
+            .find(|result| result.result.is_pass())
+            .or_else(|| self.method("dkim").next())
+    }
+
+    pub fn dmarc(&self) -> Option<&MethodResult> {
+        self.method("dmarc").next()
+    }
+}
+
+impl MessageSource {
+    /// the authentication results the receiving servers reported in the headers
+    pub fn auth_results(&self) -> AuthResults {
+        AuthResults::from_headers(self.headers())
+    }
+
+    /// checks the DKIM signatures of the message, see [`verify_dkim`]
+    #[cfg(feature = "dkim")]
+    pub async fn verify_dkim(&self, resolver: &dyn TxtResolver) -> Vec<DkimVerification> {
+        verify_dkim(self.raw(), resolver).await
+    }
+}
+
+/// parses `authserv-id; method=result reason="..." ptype.prop=value; ...`
+fn parse_authentication_results(header: &str) -> (Option<String>, Vec<MethodResult>) {
+    let header = strip_comments(header);
+    let mut statements = split_outside_quotes(&header, ';').into_iter();
+
+    let authserv_id = statements
+        .next()
+        .and_then(|id| id.split_whitespace().next().map(str::to_string));
+
+    let methods = statements.filter_map(|statement| parse_method(&statement)).collect();
+
+    (authserv_id, methods)
+}
+
+fn parse_method(statement: &str) -> Option<MethodResult> {
+    let mut tokens = split_outside_quotes(&tighten_equals(statement), ' ')
+        .into_iter()
+        .filter(|token| !token.is_empty());
+
+    let (method, result) = tokens.next()?.split_once('=').map(|(m, r)| (m.to_string(), r.to_string()))?;
+    let method = method.split('/').next().unwrap_or_default().to_ascii_lowercase();
+
+    let mut reason = None;
+    let mut properties = Vec::new();
+
+    for token in tokens {
+        let Some((name, value)) = token.split_once('=') else {
+            continue;
+        };
+        let value = unquote(value);
+
+        if name.eq_ignore_ascii_case("reason") {
+            reason = Some(value);
+        } else {
+            properties.push((name.to_ascii_lowercase(), value));
+        }
+    }
+
+    let mut result = MethodResult { method, result: AuthResult::from(result.as_str()), domain: None, reason, properties };
+    result.domain = method_domain(&result);
+
+    Some(result)
+}
+
+/// parses `pass (reason) client-ip=1.2.3.4; envelope-from=user@example.com; helo=mail.example.com;`
+fn parse_received_spf(header: &str) -> Option<MethodResult> {
+    let result = header.split_whitespace().next()?;
+    let reason = header
+        .find('(')
+        .zip(header.find(')'))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| header[start + 1..end].trim().to_string());
+
+    let properties: Vec<(String, String)> = split_outside_quotes(&strip_comments(header), ';')
+        .iter()
+        .flat_map(|part| split_outside_quotes(&tighten_equals(part), ' '))
+        .filter_map(|token| {
+            let (name, value) = token.split_once('=')?;
+            Some((name.to_ascii_lowercase(), unquote(value)))
+        })
+        .collect();
+
+    let domain = properties
+        .iter()
+        .find(|(name, _)| name == "envelope-from")
+        .or_else(|| properties.iter().find(|(name, _)| name == "helo"))
+        .map(|(_, value)| domain_of(value));
+
+    Some(MethodResult { method: "spf".to_string(), result: AuthResult::from(result), domain, reason, properties })
+}
+
+fn method_domain(result: &MethodResult) -> Option<String> {
+    let candidates: &[&str] = match result.method.as_str() {
+        "dkim" | "domainkeys" => &["header.d", "header.i"],
+        "spf" => &["smtp.mailfrom", "smtp.helo"],
+        "dmarc" => &["header.from"],
+        _ => &["header.d", "header.from", "smtp.mailfrom"],
+    };
+
+    candidates
+        .iter()
+        .find_map(|name| result.property(name))
+        .map(domain_of)
+}
+
+/// the domain part of an address, or the value itself when it has none
+fn domain_of(value: &str) -> String {
+    let value = value.trim().trim_start_matches('<').trim_end_matches('>');
+    value.rsplit('@').next().unwrap_or(value).to_ascii_lowercase()
+}
+
+/// removes `(comments)`, which may nest, outside of quoted strings
+fn strip_comments(value: &str) -> String {
+    let mut stripped = String::with_capacity(value.len());
+    let mut depth = 0usize;
+    let mut quoted = false;
+
+    for c in value.chars() {
+        match c {
+            '"' if depth == 0 => {
+                quoted = !quoted;
+                stripped.push(c);
+            }
+            '(' if !quoted => depth += 1,
+            ')' if !quoted && depth > 0 => depth -= 1,
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// `a = b` to `a=b`, so properties split into single tokens
+fn tighten_equals(value: &str) -> String {
+    let mut tightened = String::with_capacity(value.len());
+
+    for (idx, piece) in value.split('=').enumerate() {
+        if idx > 0 {
+            tightened.truncate(tightened.trim_end().len());
+            tightened.push('=');
+            tightened.push_str(piece.trim_start());
+        } else {
+            tightened.push_str(piece);
+        }
+    }
+
+    tightened
+}
+
+fn split_outside_quotes(value: &str, separator: char) -> Vec<String> {
+    let mut pieces = vec![String::new()];
+    let mut quoted = false;
+
+    for c in value.chars() {
+        let is_separator = if separator == ' ' { c.is_whitespace() } else { c == separator };
+
+        if c == '"' {
+            quoted = !quoted;
+        }
+
+        if is_separator && !quoted {
+            pieces.push(String::new());
+        } else if let Some(piece) = pieces.last_mut() {
+            piece.push(c);
+        }
+    }
+
+    pieces.into_iter().map(|piece| piece.trim().to_string()).collect()
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches('"').to_string()
+}
+
+#[cfg(feature = "dkim")]
+mod dkim {
+    use async_trait::async_trait;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use chrono::Utc;
+    use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, Pkcs1v15Sign, RsaPublicKey};
+    use serde::Serialize;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+
+    use super::AuthResult;
+    use crate::{mime::split_header, TempmailResult};
+
+    /// Looks up DNS TXT records, for the DKIM keys published at `selector._domainkey.domain`
+    ///
+    /// implemented for `HashMap<String, String>` mapping names to records, so tests can use
+    /// their own keys without any DNS
+    #[async_trait]
+    pub trait TxtResolver: Send + Sync {
+        /// every TXT record of a name, each with its strings already joined
+        async fn txt(&self, name: &str) -> TempmailResult<Vec<String>>;
+    }
+
+    #[async_trait]
+    impl TxtResolver for HashMap<String, String> {
+        async fn txt(&self, name: &str) -> TempmailResult<Vec<String>> {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            Ok(self.get(&name).cloned().into_iter().collect())
+        }
+    }
+
+    /// The outcome of checking one `DKIM-Signature` header
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    pub struct DkimVerification {
+        /// the signing domain, `d=`
+        pub domain: String,
+        /// the key selector, `s=`
+        pub selector: String,
+        /// `Pass`, `Fail` for a bad signature or body hash, `PermError` for an unusable
+        /// signature or key, like an expired one or one not covering `From`, and `TempError`
+        /// when the key couldn't be looked up
+        pub result: AuthResult,
+        pub reason: Option<String>,
+    }
+
+    struct Signature {
+        /// the raw header field, for hashing it with `b=` emptied
+        field: Vec<u8>,
+        tags: Vec<(String, String)>,
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Canonicalization {
+        Simple,
+        Relaxed,
+    }
+
+    impl Signature {
+        fn tag(&self, name: &str) -> Option<&str> {
+            self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+        }
+    }
+
+    /// checks every `DKIM-Signature` of a raw message, in header order
+    ///
+    /// only `rsa-sha256` is supported, `rsa-sha1` signatures are rejected as RFC 8301 requires.
+    /// signatures past their `x=` expiry, or whose `h=` doesn't list `From`, are rejected as
+    /// RFC 6376 requires
+    pub async fn verify_dkim(raw: &[u8], resolver: &dyn TxtResolver) -> Vec<DkimVerification> {
+        let (header_end, body_start) = split_header(raw);
+        let fields = raw_fields(&raw[..header_end]);
+        let body = &raw[body_start..];
+
+        let mut verifications = Vec::new();
+
+        for (name, field) in &fields {
+            if !name.eq_ignore_ascii_case("DKIM-Signature") {
+                continue;
+            }
+
+            let signature = Signature { field: field.clone(), tags: parse_tags(&field_value(field)) };
+            let domain = signature.tag("d").unwrap_or_default().to_ascii_lowercase();
+            let selector = signature.tag("s").unwrap_or_default().to_string();
+
+            let (result, reason) = match verify_signature(&signature, &fields, body, resolver).await {
+                Ok(()) => (AuthResult::Pass, None),
+                Err((result, reason)) => (result, Some(reason)),
+            };
+
+            verifications.push(DkimVerification { domain, selector, result, reason });
+        }
+
+        verifications
+    }
+
+    async fn verify_signature(
+        signature: &Signature,
+        fields: &[(String, Vec<u8>)],
+        body: &[u8],
+        resolver: &dyn TxtResolver,
+    ) -> Result<(), (AuthResult, String)> {
+        let perm = |reason: &str| (AuthResult::PermError, reason.to_string());
+
+        let required = |tag: &str| signature.tag(tag).ok_or_else(|| perm(&format!("missing {}= tag", tag)));
+        let (domain, selector, signed_headers) = (required("d")?, required("s")?, required("h")?);
+        let (body_hash, sig) = (required("bh")?, required("b")?);
+
+        if signature.tag("v") != Some("1") {
+            return Err(perm("unsupported version"));
+        }
+
+        match required("a")? {
+            "rsa-sha256" => {}
+            "rsa-sha1" => return Err(perm("rsa-sha1 signatures are no longer accepted")),
+            _ => return Err(perm("unsupported algorithm")),
+        }
+
+        if !signed_headers.split(':').any(|name| name.trim().eq_ignore_ascii_case("from")) {
+            return Err(perm("From isn't signed"));
+        }
+
+        if let Some(expiry) = signature.tag("x") {
+            let expiry: i64 = expiry.parse().map_err(|_| perm("invalid x= tag"))?;
+            if Utc::now().timestamp() > expiry {
+                return Err(perm("signature expired"));
+            }
+        }
+
+        let (header_canon, body_canon) = parse_canonicalization(signature.tag("c").unwrap_or("simple/simple"))
+            .ok_or_else(|| perm("unsupported canonicalization"))?;
+
+        // the body hash
+        let mut canonical_body = canonicalize_body(body, body_canon);
+        if let Some(limit) = signature.tag("l") {
+            let limit: usize = limit.parse().map_err(|_| perm("invalid l= tag"))?;
+            canonical_body.truncate(limit);
+        }
+
+        let expected_body_hash = STANDARD.decode(body_hash).map_err(|_| perm("invalid bh= tag"))?;
+        if Sha256::digest(&canonical_body).as_slice() != expected_body_hash.as_slice() {
+            return Err((AuthResult::Fail, "body hash mismatch".to_string()));
+        }
+
+        // the key
+        let name = format!("{}._domainkey.{}", selector, domain);
+        let records = resolver
+            .txt(&name)
+            .await
+            .map_err(|err| (AuthResult::TempError, format!("key lookup failed: {}", err)))?;
+        let record = records.first().ok_or_else(|| perm(&format!("no key published at {}", name)))?;
+        let key_tags = parse_tags(record);
+        let key_tag = |tag: &str| key_tags.iter().find(|(name, _)| name == tag).map(|(_, value)| value.as_str());
+
+        if key_tag("k").is_some_and(|kind| kind != "rsa") {
+            return Err(perm("unsupported key type"));
+        }
+
+        let key = match key_tag("p") {
+            None => return Err(perm("key record without p= tag")),
+            Some("") => return Err(perm("key revoked")),
+            Some(key) => STANDARD.decode(key).map_err(|_| perm("invalid key encoding"))?,
+        };
+        let key = RsaPublicKey::from_public_key_der(&key)
+            .or_else(|_| RsaPublicKey::from_pkcs1_der(&key))
+            .map_err(|_| perm("invalid key"))?;
+
+        // the header hash
+        let mut hashed = Vec::new();
+        let mut used = vec![false; fields.len()];
+
+        for name in signed_headers.split(':').map(str::trim) {
+            // repeated headers are signed from the bottom up
+            let found = fields
+                .iter()
+                .enumerate()
+                .rev()
+                .find(|(idx, (field, _))| !used[*idx] && field.eq_ignore_ascii_case(name));
+
+            if let Some((idx, (_, field))) = found {
+                used[idx] = true;
+                hashed.extend(canonicalize_header(field, header_canon));
+                hashed.extend_from_slice(b"\r\n");
+            }
+        }
+
+        hashed.extend(canonicalize_header(&without_signature(&signature.field), header_canon));
+
+        let sig = STANDARD.decode(sig).map_err(|_| perm("invalid b= tag"))?;
+        key.verify(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(&hashed), &sig)
+            .map_err(|_| (AuthResult::Fail, "signature mismatch".to_string()))
+    }
+
+    fn parse_canonicalization(value: &str) -> Option<(Canonicalization, Canonicalization)> {
+        let parse = |value: &str| match value.trim() {
+            "simple" => Some(Canonicalization::Simple),
+            "relaxed" => Some(Canonicalization::Relaxed),
+            _ => None,
+        };
+
+        match value.split_once('/') {
+            Some((header, body)) => Some((parse(header)?, parse(body)?)),
+            None => Some((parse(value)?, Canonicalization::Simple)),
+        }
+    }
+
+    /// splits a header block into its fields, keeping their folding, without the final line break
+    fn raw_fields(block: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut fields: Vec<(String, Vec<u8>)> = Vec::new();
+
+        for line in block.split_inclusive(|&b| b == b'\n') {
+            let folded = line.starts_with(b" ") || line.starts_with(b"\t");
+
+            match fields.last_mut() {
+                Some((_, field)) if folded => field.extend_from_slice(line),
+                _ => {
+                    let name = line.split(|&b| b == b':').next().unwrap_or_default();
+                    fields.push((String::from_utf8_lossy(name).trim().to_string(), line.to_vec()));
+                }
+            }
+        }
+
+        for (_, field) in &mut fields {
+            while field.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+                field.pop();
+            }
+        }
+
+        fields
+    }
+
+    fn field_value(field: &[u8]) -> String {
+        let value = field.splitn(2, |&b| b == b':').nth(1).unwrap_or_default();
+        String::from_utf8_lossy(value).into_owned()
+    }
+
+    /// parses `tag=value; tag=value` lists, dropping the whitespace inside values
+    fn parse_tags(value: &str) -> Vec<(String, String)> {
+        value
+            .split(';')
+            .filter_map(|tag| {
+                let (name, value) = tag.split_once('=')?;
+                let value = value.chars().filter(|c| !c.is_whitespace()).collect();
+                Some((name.trim().to_string(), value))
+            })
+            .collect()
+    }
+
+    /// the signature field with the value of its `b=` tag emptied, as it was when it was signed
+    fn without_signature(field: &[u8]) -> Vec<u8> {
+        let colon = field.iter().position(|&b| b == b':').map_or(field.len(), |idx| idx + 1);
+        let mut stripped = field[..colon].to_vec();
+
+        for (idx, tag) in field[colon..].split(|&b| b == b';').enumerate() {
+            if idx > 0 {
+                stripped.push(b';');
+            }
+
+            let is_signature = tag
+                .iter()
+                .position(|&b| b == b'=')
+                .is_some_and(|eq| tag[..eq].trim_ascii() == b"b");
+
+            match tag.iter().position(|&b| b == b'=') {
+                Some(eq) if is_signature => stripped.extend_from_slice(&tag[..=eq]),
+                _ => stripped.extend_from_slice(tag),
+            }
+        }
+
+        stripped
+    }
+
+    fn canonicalize_header(field: &[u8], canon: Canonicalization) -> Vec<u8> {
+        if canon == Canonicalization::Simple {
+            return field.to_vec();
+        }
+
+        let colon = field.iter().position(|&b| b == b':').unwrap_or(field.len());
+        let name = field[..colon].trim_ascii().to_ascii_lowercase();
+        let value = field.get(colon + 1..).unwrap_or_default();
+
+        let unfolded: Vec<u8> = value.iter().copied().filter(|&b| b != b'\r' && b != b'\n').collect();
+
+        let mut canonical = name;
+        canonical.push(b':');
+        canonical.extend(collapse_whitespace(&unfolded).trim_ascii());
+        canonical
+    }
+
+    fn canonicalize_body(body: &[u8], canon: Canonicalization) -> Vec<u8> {
+        let mut lines: Vec<Vec<u8>> = body
+            .split(|&b| b == b'\n')
+            .map(|line| {
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+                match canon {
+                    Canonicalization::Simple => line.to_vec(),
+                    Canonicalization::Relaxed => {
+                        let mut line = collapse_whitespace(line);
+                        line.truncate(line.trim_ascii_end().len());
+                        line
+                    }
+                }
+            })
+            .collect();
+
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+
+        if lines.is_empty() {
+            return match canon {
+                Canonicalization::Simple => b"\r\n".to_vec(),
+                Canonicalization::Relaxed => Vec::new(),
+            };
+        }
+
+        let mut canonical = lines.join(&b"\r\n"[..]);
+        canonical.extend_from_slice(b"\r\n");
+        canonical
+    }
+
+    /// turns every run of spaces and tabs into a single space
+    fn collapse_whitespace(value: &[u8]) -> Vec<u8> {
+        let mut collapsed = Vec::with_capacity(value.len());
+
+        for &b in value {
+            let is_space = b == b' ' || b == b'\t';
+
+            if !is_space {
+                collapsed.push(b);
+            } else if collapsed.last() != Some(&b' ') {
+                collapsed.push(b' ');
+            }
+        }
+
+        collapsed
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use rsa::{pkcs8::EncodePublicKey, Pkcs1v15Sign, RsaPrivateKey};
+        use sha2::{Digest, Sha256};
+        use std::{collections::HashMap, sync::OnceLock};
+
+        use super::{
+            canonicalize_body, canonicalize_header, parse_canonicalization, raw_fields, verify_dkim, without_signature,
+            Canonicalization,
+        };
+        use crate::auth::AuthResult;
+
+        const HEADERS: &str = "From: Alice <alice@example.com>\r\n\
+            To: bob@1secmail.com\r\n\
+            Subject:  Your   code\r\n\
+            \tis here\r\n";
+        const BODY: &str = "Your code is 4821  \r\n\r\n\r\n";
+
+        fn key() -> &'static RsaPrivateKey {
+            static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+            KEY.get_or_init(|| RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap())
+        }
+
+        fn resolver() -> HashMap<String, String> {
+            let public_key = key().to_public_key().to_public_key_der().unwrap();
+            let record = format!("v=DKIM1; k=rsa; p={}", STANDARD.encode(public_key.as_bytes()));
+            HashMap::from([("mail._domainkey.example.com".to_string(), record)])
+        }
+
+        /// signs `HEADERS` and `BODY` the way a sending server would, `extra` ending up in the tags
+        fn signed(canon: &str, signed_headers: &str, extra: &str) -> String {
+            let (header_canon, body_canon) = parse_canonicalization(canon).unwrap();
+            let body_hash = STANDARD.encode(Sha256::digest(canonicalize_body(BODY.as_bytes(), body_canon)));
+            let field = format!(
+                "DKIM-Signature: v=1; a=rsa-sha256; c={}; d=example.com; s=mail;{}\r\n\th={}; bh={}; b=",
+                canon, extra, signed_headers, body_hash
+            );
+
+            let fields = raw_fields(HEADERS.as_bytes());
+            let mut hashed = Vec::new();
+            for name in signed_headers.split(':') {
+                if let Some((_, field)) = fields.iter().rev().find(|(field, _)| field.eq_ignore_ascii_case(name)) {
+                    hashed.extend(canonicalize_header(field, header_canon));
+                    hashed.extend_from_slice(b"\r\n");
+                }
+            }
+            hashed.extend(canonicalize_header(field.as_bytes(), header_canon));
+
+            let sig = key().sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(&hashed)).unwrap();
+            format!("{}{}\r\n{}\r\n{}", field, STANDARD.encode(sig), HEADERS, BODY)
+        }
+
+        async fn verify(raw: &str) -> (AuthResult, Option<String>) {
+            let verifications = verify_dkim(raw.as_bytes(), &resolver()).await;
+            assert_eq!(verifications.len(), 1);
+            assert_eq!((verifications[0].domain.as_str(), verifications[0].selector.as_str()), ("example.com", "mail"));
+            (verifications[0].result.clone(), verifications[0].reason.clone())
+        }
+
+        #[tokio::test]
+        async fn passes_valid_signatures() {
+            assert_eq!(verify(&signed("relaxed/relaxed", "from:to:subject", "")).await, (AuthResult::Pass, None));
+            assert_eq!(verify(&signed("simple/simple", "From:To:Subject", "")).await, (AuthResult::Pass, None));
+            assert_eq!(verify(&signed("relaxed/simple", "from:subject", " x=4102444800;")).await, (AuthResult::Pass, None));
+        }
+
+        #[tokio::test]
+        async fn fails_tampered_messages() {
+            let tampered_body = signed("relaxed/relaxed", "from:to:subject", "").replace("4821", "4822");
+            assert_eq!(verify(&tampered_body).await, (AuthResult::Fail, Some("body hash mismatch".to_string())));
+
+            let tampered_header = signed("relaxed/relaxed", "from:to:subject", "").replace("alice@", "mallory@");
+            assert_eq!(verify(&tampered_header).await, (AuthResult::Fail, Some("signature mismatch".to_string())));
+
+            // relaxed canonicalization doesn't care about whitespace, simple does
+            let respaced = |raw: String| raw.replace("Your   code", "Your code");
+            assert_eq!(verify(&respaced(signed("relaxed/relaxed", "from:to:subject", ""))).await.0, AuthResult::Pass);
+            assert_eq!(verify(&respaced(signed("simple/simple", "from:to:subject", ""))).await.0, AuthResult::Fail);
+        }
+
+        #[tokio::test]
+        async fn rejects_expired_signatures() {
+            let expired = signed("relaxed/relaxed", "from:to:subject", " t=1500000000; x=1500086400;");
+            assert_eq!(verify(&expired).await, (AuthResult::PermError, Some("signature expired".to_string())));
+        }
+
+        #[tokio::test]
+        async fn rejects_signatures_not_covering_from() {
+            let unsigned_from = signed("relaxed/relaxed", "to:subject", "");
+            assert_eq!(verify(&unsigned_from).await, (AuthResult::PermError, Some("From isn't signed".to_string())));
+        }
+
+        #[test]
+        fn canonicalizes_headers_and_bodies() {
+            let field = b"SUBJECT : Your   code\r\n\tis  here ";
+            assert_eq!(canonicalize_header(field, Canonicalization::Relaxed), b"subject:Your code is here");
+            assert_eq!(canonicalize_header(field, Canonicalization::Simple), field);
+
+            let body = b"a  \tb \r\nc\n\r\n\r\n";
+            assert_eq!(canonicalize_body(body, Canonicalization::Relaxed), b"a b\r\nc\r\n");
+            assert_eq!(canonicalize_body(body, Canonicalization::Simple), b"a  \tb \r\nc\r\n");
+            assert_eq!(canonicalize_body(b"", Canonicalization::Simple), b"\r\n");
+            assert_eq!(canonicalize_body(b"\r\n", Canonicalization::Relaxed), b"");
+
+            assert_eq!(
+                without_signature(b"DKIM-Signature: a=rsa-sha256; bh=abc; b=c2ln\r\n\tbmF0dXJl; d=x"),
+                b"DKIM-Signature: a=rsa-sha256; bh=abc; b=; d=x"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthResult, AuthResults};
+
+    #[test]
+    fn reads_authentication_results() {
+        let raw = b"Authentication-Results: mx.1secmail.com;\r\n\
+            \tspf=pass (sender is authorized) smtp.mailfrom=bounce@mail.example.com;\r\n\
+            \tdkim=pass header.d=example.com header.s=mail header.b=\"abc;def\";\r\n\
+            \tdkim=fail reason=\"bad signature\" header.i=@other.example;\r\n\
+            \tdmarc=pass (p=reject) header.from = example.com\r\n\
+            Authentication-Results: relay.example.net; arc=none\r\n\
+            Received-SPF: softfail (mx.1secmail.com: domain does not designate 1.2.3.4)\r\n\
+            \tclient-ip=1.2.3.4; envelope-from=\"bob@spf.example\"; helo=mail.spf.example;\r\n\
+            Subject: hi\r\n\r\nbody";
+        let results = AuthResults::parse(raw);
+
+        assert_eq!(results.authserv_id.as_deref(), Some("mx.1secmail.com"));
+        let methods: Vec<&str> = results.methods.iter().map(|result| result.method.as_str()).collect();
+        assert_eq!(methods, ["spf", "dkim", "dkim", "dmarc", "arc", "spf"]);
+
+        let spf = results.spf().unwrap();
+        assert_eq!((&spf.result, spf.domain.as_deref()), (&AuthResult::Pass, Some("mail.example.com")));
+
+        let dkim = results.dkim().unwrap();
+        assert_eq!(dkim.property("header.b"), Some("abc;def"));
+        assert_eq!(dkim.property("HEADER.S"), Some("mail"));
+
+        let failed = results.method("dkim").nth(1).unwrap();
+        assert_eq!(failed.result, AuthResult::Fail);
+        assert_eq!(failed.reason.as_deref(), Some("bad signature"));
+        assert_eq!(failed.domain.as_deref(), Some("other.example"));
+
+        assert_eq!(results.dmarc().unwrap().domain.as_deref(), Some("example.com"));
+
+        let received = &results.methods[5];
+        assert_eq!(received.result, AuthResult::SoftFail);
+        assert_eq!(received.reason.as_deref(), Some("mx.1secmail.com: domain does not designate 1.2.3.4"));
+        assert_eq!(received.domain.as_deref(), Some("spf.example"));
+        assert_eq!(received.property("client-ip"), Some("1.2.3.4"));
+    }
+
+    #[test]
+    fn reads_unknown_results() {
+        let raw = b"Authentication-Results: mx.example; spf=bestguesspass smtp.helo=mail.example\n\nbody";
+        let results = AuthResults::parse(raw);
+
+        assert_eq!(results.spf().unwrap().result, AuthResult::Other("bestguesspass".to_string()));
+        assert_eq!(results.spf().unwrap().domain.as_deref(), Some("mail.example"));
+        assert!(results.dkim().is_none());
+        assert!(AuthResults::parse(b"Subject: hi\n\nbody").methods.is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+//! Blocking version of the api, for when pulling in an async runtime isn't worth it
+//!
+//! the types here mirror the ones at the crate root and return the same
+//! [`Message`]/[`RawMessage`] values
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use crate::{
+    cassette::{Cassette, Recorded},
+    client::decode_json,
+    provider::onesecmail::{download_query, messages_query, read_query, DOMAINS_QUERY},
+    parse_address, random_username, sanitize_filename, Attachment, Domain, Message, RawMessage, RetryPolicy,
+    TempmailError, TempmailResult,
+};
+
+/// Blocking counterpart of [`crate::TempmailClient`], built with
+/// [`TempmailClientBuilder::build_blocking`](crate::TempmailClientBuilder::build_blocking)
+#[derive(Clone)]
+pub struct TempmailClient {
+    http: reqwest::blocking::Client,
+    base_url: String,
+    cassette: Option<Cassette>,
+    retry: RetryPolicy,
+}
+
+/// Blocking counterpart of [`crate::Tempmail`], (de)serialized the same way
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Tempmail {
+    pub username: String,
+    pub domain: Domain,
+    #[serde(skip)]
+    client: TempmailClient,
+}
+
+impl TempmailClient {
+    pub(crate) fn new(
+        http: reqwest::blocking::Client,
+        base_url: String,
+        cassette: Option<Cassette>,
+        retry: RetryPolicy,
+    ) -> Self {
+        Self { http, base_url, cassette, retry }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// creates an inbox backed by this client
+    pub fn inbox<U>(&self, username: U, domain: Option<Domain>) -> Tempmail
+    where
+        U: Into<String>,
+    {
+        Tempmail::with_client(self.clone(), username, domain)
+    }
+
+    /// creates an inbox with a random username and domain backed by this client
+    pub fn random_inbox(&self) -> Tempmail {
+        self.inbox(random_username(), Some(Domain::random()))
+    }
+
+    /// creates an inbox with a random username on one of the domains the api currently serves
+    pub fn random_live_inbox(&self) -> TempmailResult<Tempmail> {
+        let domain = Domain::pick(self.get_domains()?)?;
+        Ok(self.inbox(random_username(), Some(domain)))
+    }
+
+    /// asks the api which domains it currently serves
+    pub fn get_domains(&self) -> TempmailResult<Vec<Domain>> {
+        let names: Vec<String> = self.reqjson(DOMAINS_QUERY)?;
+        Ok(names.into_iter().map(Domain::from_name).collect())
+    }
+
+    fn reqjson<T, R>(&self, query: T) -> TempmailResult<R>
+    where
+        T: AsRef<str>,
+        R: for<'de> Deserialize<'de>,
+    {
+        let query = query.as_ref();
+
+        self.retry.run_blocking(|| {
+            let body = self.get(query)?.text()?;
+            decode_json(&body)
+        })
+    }
+
+    fn reqbytes<T>(&self, query: T) -> TempmailResult<Vec<u8>>
+    where
+        T: AsRef<str>,
+    {
+        let query = query.as_ref();
+        self.retry.run_blocking(|| Ok(self.get(query)?.bytes()?.to_vec()))
+    }
+
+    /// only getting the response is retried, since part of the body may already be written
+    fn reqbytes_to<T>(&self, query: T, writer: &mut dyn Write) -> TempmailResult<u64>
+    where
+        T: AsRef<str>,
+    {
+        let query = query.as_ref();
+        let written = self.retry.run_blocking(|| self.get(query))?.copy_to(writer)?;
+        writer.flush()?;
+        Ok(written)
+    }
+
+    fn get<T>(&self, query: T) -> TempmailResult<reqwest::blocking::Response>
+    where
+        T: AsRef<str>,
+    {
+        let query = query.as_ref();
+        let req = self.http.get(format!("{}?{}", self.base_url, query));
+
+        let res = match &self.cassette {
+            None => req.send()?,
+            Some(cassette) => match cassette.lookup(query)? {
+                Some(recorded) => recorded.into_blocking_response(),
+                None => {
+                    let res = req.send()?;
+                    let recorded = Recorded { status: res.status(), headers: res.headers().clone(), body: res.bytes()?.to_vec() };
+                    cassette.record_response(query, &recorded)?;
+                    recorded.into_blocking_response()
+                }
+            },
+        };
+
+        if !res.status().is_success() {
+            let status = res.status();
+            let headers = res.headers().clone();
+            let body = res.text().unwrap_or_default();
+            return Err(TempmailError::from_response(status, &headers, &body));
+        }
+
+        Ok(res)
+    }
+}
+
+impl Default for TempmailClient {
+    /// returns a client with the default configuration, shared by every inbox created
+    /// through [`Tempmail::new`] and [`Tempmail::random`]
+    fn default() -> Self {
+        static DEFAULT: OnceLock<TempmailClient> = OnceLock::new();
+
+        DEFAULT
+            .get_or_init(|| {
+                crate::TempmailClient::builder()
+                    .build_blocking()
+                    .expect("failed to build the default tempmail client")
+            })
+            .clone()
+    }
+}
+
+impl Tempmail {
+    pub fn new<U>(username: U, domain: Option<Domain>) -> Self
+    where
+        U: Into<String>,
+    {
+        Self::with_client(TempmailClient::default(), username, domain)
+    }
+
+    /// creates an inbox that sends its requests through the given client
+    pub fn with_client<U>(client: TempmailClient, username: U, domain: Option<Domain>) -> Self
+    where
+        U: Into<String>,
+    {
+        Self { username: username.into(), domain: domain.unwrap_or_default(), client }
+    }
+
+    pub fn random() -> Self {
+        TempmailClient::default().random_inbox()
+    }
+
+    /// like [`Tempmail::random`], but picks the domain from the ones the api currently serves
+    pub fn random_live() -> TempmailResult<Self> {
+        TempmailClient::default().random_live_inbox()
+    }
+
+    pub fn client(&self) -> &TempmailClient {
+        &self.client
+    }
+
+    /// the full email address of this inbox
+    pub fn address(&self) -> String {
+        self.to_string()
+    }
+
+    pub fn get_raw_messages(&self) -> TempmailResult<Vec<RawMessage>> {
+        self.client.reqjson(messages_query(&self.username, &self.domain))
+    }
+
+    pub fn get_messages(&self) -> TempmailResult<Vec<Message>> {
+        self.get_raw_messages()?
+            .iter()
+            .map(|raw_msg| self.read_raw_messsage(raw_msg))
+            .collect()
+    }
+
+    pub fn read_raw_messsage(&self, raw_msg: &RawMessage) -> TempmailResult<Message> {
+        let msg: Message = self.client.reqjson(read_query(&self.username, &self.domain, raw_msg.id))?;
+
+        Ok(msg.normalized())
+    }
+
+    /// gets attachment of a msg_id and filename
+    pub fn get_attachment<T>(&self, msg_id: usize, filename: T) -> TempmailResult<Vec<u8>>
+    where
+        T: AsRef<str>,
+    {
+        self.client.reqbytes(download_query(&self.username, &self.domain, msg_id, filename.as_ref()))
+    }
+
+    /// streams an attachment into a writer instead of buffering it, returns how many bytes were written
+    pub fn get_attachment_to<T, W>(&self, msg_id: usize, filename: T, writer: &mut W) -> TempmailResult<u64>
+    where
+        T: AsRef<str>,
+        W: Write,
+    {
+        self.client
+            .reqbytes_to(download_query(&self.username, &self.domain, msg_id, filename.as_ref()), writer)
+    }
+
+    /// streams an attachment into a file in `dir`, see [`crate::Tempmail::save_attachment`]
+    pub fn save_attachment<D>(&self, msg_id: usize, attachment: &Attachment, dir: D) -> TempmailResult<PathBuf>
+    where
+        D: AsRef<Path>,
+    {
+        let path = dir.as_ref().join(sanitize_filename(&attachment.filename));
+        let mut file = File::create(&path)?;
+
+        let res = self.get_attachment_to(msg_id, &attachment.filename, &mut file);
+        drop(file);
+
+        let res = res.and_then(|written| match written == attachment.size as u64 {
+            true => Ok(path.clone()),
+            false => Err(TempmailError::SizeMismatch { expected: attachment.size, actual: written }),
+        });
+
+        if res.is_err() {
+            let _ = std::fs::remove_file(&path);
+        }
+
+        res
+    }
+}
+
+impl FromStr for Tempmail {
+    type Err = TempmailError;
+
+    /// parses a `username@domain` address into an inbox using the default client
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (username, domain) = parse_address(s)?;
+        Ok(Self::new(username, Some(domain)))
+    }
+}
+
+impl Display for Tempmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.username, self.domain)
+    }
+}
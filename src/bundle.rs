@@ -0,0 +1,250 @@
+use futures::{stream, StreamExt, TryStreamExt};
+use std::path::{Path, PathBuf};
+
+use crate::{sanitize_filename, Message, Tempmail, TempmailResult};
+
+/// how many attachments of a message are downloaded at once by default
+pub const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 4;
+
+/// the name the raw source of the message is saved as, numbered like attachments if one has it
+const SOURCE_FILENAME: &str = "message.eml";
+
+/// An attachment with its content
+#[derive(Clone, Debug)]
+pub struct DownloadedAttachment {
+    /// the filename the attachment was sent with
+    pub original_filename: String,
+    /// the filename stripped of paths and unsafe characters, unique within its bundle
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Every attachment of a message, in the order the message lists them
+///
+/// with [`Message::download_bundle`] it also holds the raw source of the message, saved next
+/// to the attachments as `message.eml`
+#[derive(Clone, Debug, Default)]
+pub struct AttachmentBundle {
+    pub attachments: Vec<DownloadedAttachment>,
+    pub source: Option<Vec<u8>>,
+}
+
+impl AttachmentBundle {
+    pub fn len(&self) -> usize {
+        self.attachments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attachments.is_empty()
+    }
+
+    /// finds an attachment by its sanitized or its original filename
+    pub fn get(&self, filename: &str) -> Option<&DownloadedAttachment> {
+        self.attachments
+            .iter()
+            .find(|att| att.filename == filename)
+            .or_else(|| self.attachments.iter().find(|att| att.original_filename == filename))
+    }
+
+    /// the filenames and contents of the files the bundle is saved as: the attachments, then
+    /// the source if there's one
+    pub fn files(&self) -> Vec<(String, &[u8])> {
+        let mut files: Vec<(String, &[u8])> =
+            self.attachments.iter().map(|att| (att.filename.clone(), att.data.as_slice())).collect();
+
+        if let Some(source) = &self.source {
+            let names = unique_filenames(files.iter().map(|(name, _)| name.clone()).chain([SOURCE_FILENAME.to_string()]));
+            files.push((names.last().cloned().unwrap_or_default(), source.as_slice()));
+        }
+
+        files
+    }
+
+    /// writes every file of the bundle into `dir`, creating it if needed, and returns the paths written
+    pub async fn save_to_dir<D>(&self, dir: D) -> TempmailResult<Vec<PathBuf>>
+    where
+        D: AsRef<Path>,
+    {
+        tokio::fs::create_dir_all(dir.as_ref()).await?;
+
+        let files = self.files();
+        let mut paths = Vec::with_capacity(files.len());
+
+        for (filename, data) in files {
+            let path = dir.as_ref().join(filename);
+            tokio::fs::write(&path, data).await?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+
+    /// packs every file of the bundle into a deflated zip archive
+    #[cfg(feature = "zip")]
+    pub fn to_zip(&self) -> TempmailResult<Vec<u8>> {
+        let mut archive = std::io::Cursor::new(Vec::new());
+        self.write_zip(&mut archive)?;
+        Ok(archive.into_inner())
+    }
+
+    /// like [`AttachmentBundle::to_zip`], writing the archive into any seekable writer, like a file
+    #[cfg(feature = "zip")]
+    pub fn write_zip<W>(&self, writer: W) -> TempmailResult<()>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        use std::io::Write;
+        use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+        let mut zip = ZipWriter::new(writer);
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (filename, data) in self.files() {
+            zip.start_file(filename, options).map_err(zip_error)?;
+            zip.write_all(data)?;
+        }
+
+        zip.finish().map_err(zip_error)?;
+        Ok(())
+    }
+}
+
+impl IntoIterator for AttachmentBundle {
+    type Item = DownloadedAttachment;
+    type IntoIter = std::vec::IntoIter<DownloadedAttachment>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.attachments.into_iter()
+    }
+}
+
+impl Message {
+    /// downloads every attachment of the message from the inbox it was read from, a few at a time
+    pub async fn download_attachments(&self, inbox: &Tempmail) -> TempmailResult<AttachmentBundle> {
+        self.download_attachments_with_limit(inbox, DEFAULT_DOWNLOAD_CONCURRENCY).await
+    }
+
+    /// like [`Message::download_attachments`], with at most `limit` downloads running at once
+    pub async fn download_attachments_with_limit(&self, inbox: &Tempmail, limit: usize) -> TempmailResult<AttachmentBundle> {
+        let downloads = self
+            .attachments
+            .iter()
+            .map(|att| async move { inbox.get_attachment(self.id, &att.filename).await });
+
+        let data: Vec<Vec<u8>> = stream::iter(downloads).buffered(limit.max(1)).try_collect().await?;
+
+        let filenames = unique_filenames(self.attachments.iter().map(|att| sanitize_filename(&att.filename)));
+
+        let attachments = self
+            .attachments
+            .iter()
+            .zip(filenames)
+            .zip(data)
+            .map(|((att, filename), data)| DownloadedAttachment {
+                original_filename: att.filename.clone(),
+                filename,
+                content_type: att.content_type.clone(),
+                data,
+            })
+            .collect();
+
+        Ok(AttachmentBundle { attachments, source: None })
+    }
+
+    /// like [`Message::download_attachments`], also fetching the raw source of the message
+    pub async fn download_bundle(&self, inbox: &Tempmail) -> TempmailResult<AttachmentBundle> {
+        let mut bundle = self.download_attachments(inbox).await?;
+        bundle.source = Some(inbox.get_source(self.id).await?.into_raw());
+        Ok(bundle)
+    }
+}
+
+/// numbers repeated filenames, `a.pdf`, `a (2).pdf`, `a (3).pdf`, ignoring case
+fn unique_filenames<I>(filenames: I) -> Vec<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut unique: Vec<String> = Vec::new();
+
+    for filename in filenames {
+        let (stem, ext) = match filename.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+            _ => (filename.clone(), String::new()),
+        };
+
+        let mut candidate = filename;
+        let mut n = 2;
+
+        while unique.iter().any(|taken| taken.eq_ignore_ascii_case(&candidate)) {
+            candidate = format!("{} ({}){}", stem, n, ext);
+            n += 1;
+        }
+
+        unique.push(candidate);
+    }
+
+    unique
+}
+
+#[cfg(feature = "zip")]
+fn zip_error(err: zip::result::ZipError) -> crate::TempmailError {
+    match err {
+        zip::result::ZipError::Io(err) => crate::TempmailError::Io(err),
+        err => crate::TempmailError::Io(std::io::Error::other(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unique_filenames;
+    use crate::testing::{MockMessage, MockServer};
+
+    #[test]
+    fn numbers_repeated_filenames() {
+        let filenames = ["a.pdf", "A.pdf", "a.pdf", "notes", "notes", ".env"].map(str::to_string);
+        assert_eq!(unique_filenames(filenames), ["a.pdf", "A (2).pdf", "a (3).pdf", "notes", "notes (2)", ".env"]);
+    }
+
+    #[cfg(feature = "zip")]
+    #[tokio::test]
+    async fn zips_the_attachments_and_the_source() {
+        use std::io::Read;
+
+        let server = MockServer::start().unwrap();
+        let inbox = server.random_inbox();
+        server.mailbox().deliver(
+            &inbox.address(),
+            MockMessage::new("alice@example.com", "files")
+                .text("see attached")
+                .attachment("../../etc/report.pdf", "application/pdf", vec![1, 2, 3])
+                .attachment("Report.pdf", "application/pdf", vec![4, 5])
+                .attachment("a<b>:c?.txt", "text/plain", "odd name")
+                .attachment("message.eml", "message/rfc822", "forwarded"),
+        );
+
+        let msg = inbox.get_messages().await.unwrap().remove(0);
+        let bundle = msg.download_bundle(&inbox).await.unwrap();
+        let source = inbox.get_source(msg.id).await.unwrap().into_raw();
+
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bundle.to_zip().unwrap())).unwrap();
+        let mut entries = Vec::new();
+        for idx in 0..zip.len() {
+            let mut entry = zip.by_index(idx).unwrap();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            entries.push((entry.name().to_string(), data));
+        }
+
+        assert_eq!(
+            entries,
+            [
+                ("report.pdf".to_string(), vec![1, 2, 3]),
+                ("Report (2).pdf".to_string(), vec![4, 5]),
+                ("abc.txt".to_string(), b"odd name".to_vec()),
+                ("message.eml".to_string(), b"forwarded".to_vec()),
+                ("message (2).eml".to_string(), source),
+            ]
+        );
+    }
+}
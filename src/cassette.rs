@@ -0,0 +1,372 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, RETRY_AFTER},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{TempmailError, TempmailResult};
+
+/// the response headers worth replaying, the others are left out of the cassette
+const RECORDED_HEADERS: [HeaderName; 2] = [CONTENT_TYPE, RETRY_AFTER];
+
+/// How a [`Cassette`] treats requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// every request goes to the api and its response is recorded, replacing the cassette's content
+    Record,
+    /// recorded requests are answered from the cassette, the others go to the api and get recorded
+    Replay,
+    /// recorded requests are answered from the cassette, the others fail with
+    /// [`TempmailError::NotRecorded`]
+    Strict,
+}
+
+/// Recorded api responses, to replay them instead of talking to the api
+///
+/// requests are matched on their `action`, `login`, `domain` and `id` params (and `file` for
+/// attachment downloads). a request recorded several times, like a polled `getMessages`, is
+/// answered with its recordings in order, the last one repeating once they're used up. the
+/// status, body and the `Content-Type` and `Retry-After` headers of responses are recorded
+///
+/// the cassette is a json file, saved after every new recording. cloning is cheap and every
+/// clone shares the same recordings
+#[derive(Clone)]
+pub struct Cassette {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    path: PathBuf,
+    mode: CassetteMode,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    interactions: Vec<Interaction>,
+    /// how many recordings of each request were replayed so far
+    replayed: HashMap<RequestKey, usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+struct RequestKey {
+    action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    login: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    domain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Interaction {
+    request: RequestKey,
+    status: u16,
+    /// lowercase names, only the ones in `RECORDED_HEADERS`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<(String, String)>,
+    body: String,
+    /// the body isn't utf-8, like most attachments, and is stored as base64
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    base64: bool,
+}
+
+/// A response taken from a cassette, or about to be recorded in one
+pub(crate) struct Recorded {
+    pub(crate) status: StatusCode,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Cassette {
+    /// opens the cassette at `path`
+    ///
+    /// in [`CassetteMode::Record`] the file doesn't need to exist and is overwritten, in
+    /// [`CassetteMode::Replay`] a missing file is an empty cassette, and in
+    /// [`CassetteMode::Strict`] it has to exist
+    pub fn open<P>(path: P, mode: CassetteMode) -> TempmailResult<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+
+        let interactions = match (mode, std::fs::read(&path)) {
+            (CassetteMode::Record, _) => Vec::new(),
+            (CassetteMode::Replay, Err(err)) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            (_, Err(err)) => return Err(TempmailError::Io(err)),
+            (_, Ok(raw)) => serde_json::from_slice(&raw)
+                .map_err(|err| TempmailError::decode(err, &String::from_utf8_lossy(&raw)))?,
+        };
+
+        let state = State { interactions, replayed: HashMap::new() };
+        Ok(Self { inner: Arc::new(Inner { path, mode, state: Mutex::new(state) }) })
+    }
+
+    /// shorthand for opening in [`CassetteMode::Record`]
+    pub fn record<P>(path: P) -> TempmailResult<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        Self::open(path, CassetteMode::Record)
+    }
+
+    /// shorthand for opening in [`CassetteMode::Replay`]
+    pub fn replay<P>(path: P) -> TempmailResult<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        Self::open(path, CassetteMode::Replay)
+    }
+
+    /// shorthand for opening in [`CassetteMode::Strict`]
+    pub fn strict<P>(path: P) -> TempmailResult<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        Self::open(path, CassetteMode::Strict)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.inner.mode
+    }
+
+    /// how many responses are recorded
+    pub fn len(&self) -> usize {
+        self.state().interactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.inner.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// the recorded response to a query, `None` when it has to go to the api
+    pub(crate) fn lookup(&self, query: &str) -> TempmailResult<Option<Recorded>> {
+        if self.inner.mode == CassetteMode::Record {
+            return Ok(None);
+        }
+
+        let key = RequestKey::parse(query);
+        let mut state = self.state();
+
+        let recordings: Vec<&Interaction> = state
+            .interactions
+            .iter()
+            .filter(|interaction| interaction.request == key)
+            .collect();
+
+        let Some(last) = recordings.len().checked_sub(1) else {
+            return match self.inner.mode {
+                CassetteMode::Strict => Err(TempmailError::NotRecorded(query.to_string())),
+                _ => Ok(None),
+            };
+        };
+
+        let replayed = state.replayed.get(&key).copied().unwrap_or_default();
+        let recorded = recordings[replayed.min(last)].to_recorded()?;
+        state.replayed.insert(key, replayed + 1);
+
+        Ok(Some(recorded))
+    }
+
+    /// records the api's response to a query and saves the cassette
+    pub(crate) fn record_response(&self, query: &str, recorded: &Recorded) -> TempmailResult<()> {
+        let (body, base64) = match std::str::from_utf8(&recorded.body) {
+            Ok(body) => (body.to_string(), false),
+            Err(_) => (STANDARD.encode(&recorded.body), true),
+        };
+
+        let headers = RECORDED_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = recorded.headers.get(name)?.to_str().ok()?;
+                Some((name.as_str().to_string(), value.to_string()))
+            })
+            .collect();
+
+        let interaction =
+            Interaction { request: RequestKey::parse(query), status: recorded.status.as_u16(), headers, body, base64 };
+
+        let mut state = self.state();
+        state.interactions.push(interaction);
+
+        let json = serde_json::to_vec_pretty(&state.interactions)
+            .map_err(|err| TempmailError::Io(std::io::Error::other(err)))?;
+        std::fs::write(&self.inner.path, json)?;
+
+        Ok(())
+    }
+}
+
+impl RequestKey {
+    fn parse(query: &str) -> Self {
+        let Ok(url) = reqwest::Url::parse(&format!("http://cassette/?{}", query)) else {
+            return Self::default();
+        };
+
+        let mut key = Self::default();
+
+        for (name, value) in url.query_pairs() {
+            let value = value.into_owned();
+
+            match name.as_ref() {
+                "action" => key.action = value,
+                "login" => key.login = Some(value),
+                "domain" => key.domain = Some(value),
+                "id" => key.id = Some(value),
+                "file" => key.file = Some(value),
+                _ => {}
+            }
+        }
+
+        key
+    }
+}
+
+impl Interaction {
+    fn to_recorded(&self) -> TempmailResult<Recorded> {
+        let status = StatusCode::from_u16(self.status)
+            .map_err(|err| TempmailError::decode(err, &self.status.to_string()))?;
+
+        let body = match self.base64 {
+            true => STANDARD.decode(&self.body).map_err(|err| TempmailError::decode(err, &self.body))?,
+            false => self.body.clone().into_bytes(),
+        };
+
+        let headers = self
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((HeaderName::from_bytes(name.as_bytes()).ok()?, HeaderValue::from_str(value).ok()?))
+            })
+            .collect();
+
+        Ok(Recorded { status, headers, body })
+    }
+}
+
+impl Recorded {
+    pub(crate) fn into_response(self) -> reqwest::Response {
+        reqwest::Response::from(self.into_http())
+    }
+
+    #[cfg(feature = "blocking")]
+    pub(crate) fn into_blocking_response(self) -> reqwest::blocking::Response {
+        reqwest::blocking::Response::from(self.into_http())
+    }
+
+    fn into_http(self) -> http::Response<Vec<u8>> {
+        let mut res = http::Response::new(self.body);
+        *res.status_mut() = self.status;
+        *res.headers_mut() = self.headers;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::Cassette;
+    use crate::{
+        random_string,
+        testing::{MockMessage, MockServer},
+        Domain, RetryPolicy, TempmailClient, TempmailError,
+    };
+
+    fn cassette_path() -> PathBuf {
+        std::env::temp_dir().join(format!("tempmail-cassette-{}.json", random_string(12)))
+    }
+
+    fn client(base_url: String, cassette: Cassette) -> TempmailClient {
+        TempmailClient::builder()
+            .base_url(base_url)
+            .cassette(cassette)
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_what_it_recorded() {
+        let path = cassette_path();
+        let server = MockServer::start().unwrap();
+        let base_url = server.base_url();
+        let message = MockMessage::new("alice@example.com", "hi").attachment("a.bin", "application/octet-stream", vec![0xff, 0]);
+        server.mailbox().deliver("bob@1secmail.com", message);
+
+        let inbox = client(base_url.clone(), Cassette::record(&path).unwrap()).inbox("bob", Some(Domain::SecMailCom));
+        let recorded = inbox.get_messages().await.unwrap();
+        assert_eq!(inbox.get_attachment(1, "a.bin").await.unwrap(), [0xff, 0]);
+        drop(server);
+
+        let saved: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved[0]["request"]["action"], "getMessages");
+        assert_eq!(saved[0]["headers"][0], serde_json::json!(["content-type", "application/json"]));
+
+        // the server is gone, so everything has to come from the cassette
+        let cassette = Cassette::strict(&path).unwrap();
+        assert_eq!(cassette.len(), 3);
+        let inbox = client(base_url, cassette).inbox("bob", Some(Domain::SecMailCom));
+
+        let replayed = inbox.get_messages().await.unwrap();
+        assert_eq!(replayed.len(), 1);
+        assert_eq!((replayed[0].id, &replayed[0].subject), (1, &recorded[0].subject));
+        assert_eq!(replayed[0].timestamp, recorded[0].timestamp);
+        assert_eq!(inbox.get_attachment(1, "a.bin").await.unwrap(), [0xff, 0]);
+
+        let missed = inbox.get_attachment(1, "b.bin").await;
+        assert!(matches!(missed, Err(TempmailError::NotRecorded(query)) if query.contains("file=b.bin")));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replays_retry_after() {
+        let path = cassette_path();
+        let interactions = serde_json::json!([
+            {
+                "request": { "action": "getMessages", "login": "bob", "domain": "1secmail.com" },
+                "status": 429,
+                "headers": [["retry-after", "7"]],
+                "body": "",
+            },
+            {
+                "request": { "action": "readMessage", "login": "bob", "domain": "1secmail.com", "id": "1" },
+                "status": 503,
+                "headers": [["content-type", "text/plain"], ["retry-after", "120"]],
+                "body": "down for maintenance",
+            },
+        ]);
+        std::fs::write(&path, interactions.to_string()).unwrap();
+
+        let inbox = client("http://127.0.0.1:9/api/v1/".to_string(), Cassette::strict(&path).unwrap())
+            .inbox("bob", Some(Domain::SecMailCom));
+
+        let err = inbox.get_raw_messages().await.unwrap_err();
+        assert!(matches!(err, TempmailError::RateLimited { .. }));
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(7)));
+
+        let err = inbox.provider().read_message("bob", &Domain::SecMailCom, 1).await.unwrap_err();
+        assert_eq!(err.status(), Some(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(120)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,388 @@
+use serde::Deserialize;
+use std::{sync::OnceLock, time::Duration};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    cassette::{Cassette, Recorded},
+    provider::OneSecMail,
+    random_username, Domain, MailProvider, RetryPolicy, Tempmail, TempmailError, TempmailResult,
+};
+
+/// what 1secmail answers with instead of json when an id doesn't exist
+pub(crate) const NOT_FOUND_BODY: &str = "Message not found";
+
+const API_URL: &str = "https://www.1secmail.com/api/v1/";
+
+/// Represents a configured connection to the 1secmail api
+///
+/// cloning is cheap and every clone shares the same connection pool, so one client
+/// can back as many inboxes as you want
+#[derive(Clone)]
+pub struct TempmailClient {
+    http: reqwest::Client,
+    base_url: String,
+    cassette: Option<Cassette>,
+    retry: RetryPolicy,
+}
+
+/// Builder for a [`TempmailClient`]
+pub struct TempmailClientBuilder {
+    base_url: String,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    proxy: Option<reqwest::Proxy>,
+    cassette: Option<Cassette>,
+    retry: RetryPolicy,
+}
+
+impl TempmailClientBuilder {
+    /// sets the api base url, defaults to `https://www.1secmail.com/api/v1/`
+    pub fn base_url<U>(mut self, base_url: U) -> Self
+    where
+        U: Into<String>,
+    {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// sets the timeout for a whole request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// sets the timeout for the connect phase of a request
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// sets the `User-Agent` header sent with every request
+    pub fn user_agent<U>(mut self, user_agent: U) -> Self
+    where
+        U: Into<String>,
+    {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// routes every request through the given proxy
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// records the api's responses into a cassette or replays them from it, depending on its mode
+    pub fn cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    /// sets how failed api calls are retried, [`RetryPolicy::none`] turning retrying off
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn build(self) -> TempmailResult<TempmailClient> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        if let Some(user_agent) = self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy);
+        }
+
+        Ok(TempmailClient { http: builder.build()?, base_url: self.base_url, cassette: self.cassette, retry: self.retry })
+    }
+
+    /// builds a client for the [`blocking`](crate::blocking) api from the same settings
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(self) -> TempmailResult<crate::blocking::TempmailClient> {
+        let mut builder = reqwest::blocking::Client::builder();
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        if let Some(user_agent) = self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy);
+        }
+
+        Ok(crate::blocking::TempmailClient::new(builder.build()?, self.base_url, self.cassette, self.retry))
+    }
+}
+
+impl Default for TempmailClientBuilder {
+    fn default() -> Self {
+        Self {
+            base_url: API_URL.to_string(),
+            timeout: None,
+            connect_timeout: None,
+            user_agent: None,
+            proxy: None,
+            cassette: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl TempmailClient {
+    pub fn builder() -> TempmailClientBuilder {
+        TempmailClientBuilder::default()
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// returns a client talking to another base url, sharing this one's connection pool
+    pub fn with_base_url<U>(&self, base_url: U) -> TempmailClient
+    where
+        U: Into<String>,
+    {
+        TempmailClient {
+            http: self.http.clone(),
+            base_url: base_url.into(),
+            cassette: self.cassette.clone(),
+            retry: self.retry.clone(),
+        }
+    }
+
+    /// returns a client retrying with another policy, sharing this one's connection pool
+    pub fn with_retry_policy(&self, retry: RetryPolicy) -> TempmailClient {
+        TempmailClient { retry, ..self.clone() }
+    }
+
+    /// creates an inbox backed by this client
+    pub fn inbox<U>(&self, username: U, domain: Option<Domain>) -> Tempmail
+    where
+        U: Into<String>,
+    {
+        Tempmail::with_client(self.clone(), username, domain)
+    }
+
+    /// creates an inbox with a random username and domain backed by this client
+    pub fn random_inbox(&self) -> Tempmail {
+        self.inbox(random_username(), Some(Domain::random()))
+    }
+
+    /// creates an inbox with a random username on one of the domains the api currently serves
+    pub async fn random_live_inbox(&self) -> TempmailResult<Tempmail> {
+        let domain = Domain::pick(self.get_domains().await?)?;
+        Ok(self.inbox(random_username(), Some(domain)))
+    }
+
+    /// asks the api which domains it currently serves
+    pub async fn get_domains(&self) -> TempmailResult<Vec<Domain>> {
+        OneSecMail::new(self.clone()).list_domains().await
+    }
+
+    /// function to do a json get req and deserialize it
+    pub(crate) async fn reqjson<T, R>(&self, query: T) -> TempmailResult<R>
+    where
+        T: AsRef<str>,
+        R: for<'de> Deserialize<'de>,
+    {
+        let query = query.as_ref();
+
+        self.retry
+            .run(|| async {
+                let body = self.get(query).await?.text().await?;
+                decode_json(&body)
+            })
+            .await
+    }
+
+    /// function to do a get req and return the raw body
+    pub(crate) async fn reqbytes<T>(&self, query: T) -> TempmailResult<Vec<u8>>
+    where
+        T: AsRef<str>,
+    {
+        let query = query.as_ref();
+        self.retry.run(|| async { Ok(self.get(query).await?.bytes().await?.to_vec()) }).await
+    }
+
+    /// like `reqbytes`, but streams the body into a writer instead of buffering it
+    ///
+    /// only getting the response is retried, since part of the body may already be written
+    pub(crate) async fn reqbytes_to<T>(&self, query: T, writer: &mut (dyn AsyncWrite + Unpin + Send)) -> TempmailResult<u64>
+    where
+        T: AsRef<str>,
+    {
+        let query = query.as_ref();
+        write_body(self.retry.run(|| self.get(query)).await?, writer).await
+    }
+
+    /// does a get req against the web mailbox, which serves what the api doesn't
+    pub(crate) async fn reqsource<T>(&self, query: T) -> TempmailResult<Vec<u8>>
+    where
+        T: AsRef<str>,
+    {
+        let url = self.mailbox_url()?;
+        let query = query.as_ref();
+        let raw = self.retry.run(|| async { Ok(self.fetch(url.as_str(), query).await?.bytes().await?) }).await?;
+
+        if raw.trim_ascii() == NOT_FOUND_BODY.as_bytes() {
+            return Err(TempmailError::NotFound);
+        }
+
+        Ok(raw.to_vec())
+    }
+
+    /// the mailbox lives at `/mailbox/`, next to the `/api/v1/` the base url points to
+    fn mailbox_url(&self) -> TempmailResult<reqwest::Url> {
+        reqwest::Url::parse(&self.base_url)
+            .and_then(|url| url.join("../../mailbox/"))
+            .map_err(|err| TempmailError::InvalidInput(format!("bad base url {}: {}", self.base_url, err)))
+    }
+
+    async fn get<T>(&self, query: T) -> TempmailResult<reqwest::Response>
+    where
+        T: AsRef<str>,
+    {
+        self.fetch(&self.base_url, query.as_ref()).await
+    }
+
+    /// does a get req for a query, going through the cassette if there's one
+    async fn fetch(&self, url: &str, query: &str) -> TempmailResult<reqwest::Response> {
+        let req = self.http.get(format!("{}?{}", url, query));
+
+        let Some(cassette) = &self.cassette else {
+            return check(req.send().await?).await;
+        };
+
+        let recorded = match cassette.lookup(query)? {
+            Some(recorded) => recorded,
+            None => {
+                let res = req.send().await?;
+                let recorded = Recorded { status: res.status(), headers: res.headers().clone(), body: res.bytes().await?.to_vec() };
+                cassette.record_response(query, &recorded)?;
+                recorded
+            }
+        };
+
+        check(recorded.into_response()).await
+    }
+
+    pub(crate) fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    /// sends a request and deserializes the json it answers with
+    pub(crate) async fn sendjson<R>(&self, req: reqwest::RequestBuilder) -> TempmailResult<R>
+    where
+        R: for<'de> Deserialize<'de>,
+    {
+        if !can_retry(&req) {
+            return decode_json(&check(req.send().await?).await?.text().await?);
+        }
+
+        self.retry
+            .run(|| async {
+                let body = check(resend(&req).send().await?).await?.text().await?;
+                decode_json(&body)
+            })
+            .await
+    }
+
+    /// sends a request, turning non-success responses into errors
+    ///
+    /// only idempotent requests are retried, a `POST` that timed out may still have created
+    /// something and sending it again would fail or create it twice
+    pub(crate) async fn send(&self, req: reqwest::RequestBuilder) -> TempmailResult<reqwest::Response> {
+        if !can_retry(&req) {
+            return check(req.send().await?).await;
+        }
+
+        self.retry.run(|| async { check(resend(&req).send().await?).await }).await
+    }
+}
+
+/// deserializes an api response body, telling missing messages apart from garbage
+pub(crate) fn decode_json<R>(body: &str) -> TempmailResult<R>
+where
+    R: for<'de> Deserialize<'de>,
+{
+    if body.trim() == NOT_FOUND_BODY {
+        return Err(TempmailError::NotFound);
+    }
+
+    serde_json::from_str(body).map_err(|err| TempmailError::decode(err, body))
+}
+
+impl Default for TempmailClient {
+    /// returns a client with the default configuration, shared by every inbox created
+    /// through [`Tempmail::new`] and [`Tempmail::random`]
+    fn default() -> Self {
+        static DEFAULT: OnceLock<TempmailClient> = OnceLock::new();
+
+        DEFAULT
+            .get_or_init(|| {
+                TempmailClient::builder()
+                    .build()
+                    .expect("failed to build the default tempmail client")
+            })
+            .clone()
+    }
+}
+
+/// whether a request can be sent again: it's idempotent and its body isn't streamed
+fn can_retry(req: &reqwest::RequestBuilder) -> bool {
+    req.try_clone()
+        .and_then(|req| req.build().ok())
+        .is_some_and(|req| req.method().is_idempotent())
+}
+
+/// a copy of a request known to be cloneable, to send it again
+fn resend(req: &reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    req.try_clone().expect("request was cloned before")
+}
+
+/// turns non-success responses into errors
+async fn check(res: reqwest::Response) -> TempmailResult<reqwest::Response> {
+    if !res.status().is_success() {
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = res.text().await.unwrap_or_default();
+        return Err(TempmailError::from_response(status, &headers, &body));
+    }
+
+    Ok(res)
+}
+
+/// streams the body of a response into a writer, returning how many bytes were written
+pub(crate) async fn write_body(mut res: reqwest::Response, writer: &mut (dyn AsyncWrite + Unpin + Send)) -> TempmailResult<u64> {
+    let mut written = 0;
+
+    while let Some(chunk) = res.chunk().await? {
+        writer.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+
+    writer.flush().await?;
+    Ok(written)
+}
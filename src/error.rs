@@ -0,0 +1,207 @@
+use reqwest::{header::HeaderMap, StatusCode};
+use std::{fmt::Display, time::Duration};
+
+/// max number of characters of a response body kept around in an error
+const SNIPPET_LEN: usize = 256;
+
+/// Represents everything that can go wrong while talking to a temp mail api
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TempmailError {
+    /// the request never made it to the api, or the response couldn't be read
+    Transport(reqwest::Error),
+    /// the api answered with a non-success status code, `retry_after` coming from its
+    /// `Retry-After` header (usually sent with a 503)
+    Status { status: StatusCode, body: String, retry_after: Option<Duration> },
+    /// the api told us to slow down (http 429)
+    RateLimited { retry_after: Option<Duration> },
+    /// the api answered, but not with something we could make sense of
+    Decode { message: String, body: String },
+    /// the requested message (or attachment) doesn't exist
+    NotFound,
+    /// something passed to the crate was invalid
+    InvalidInput(String),
+    /// gave up waiting after the given duration
+    Timeout(Duration),
+    /// the domain isn't one the crate knows about
+    UnknownDomain(String),
+    /// the email address (or its username) isn't valid
+    InvalidAddress(String),
+    /// the provider can't do what was asked
+    Unsupported(&'static str),
+    /// none of the providers that could handle the request are up, or the service serves no
+    /// domains to create addresses on
+    Unavailable,
+    /// writing a download out failed
+    Io(std::io::Error),
+    /// a download didn't have the size the message said it would
+    SizeMismatch { expected: usize, actual: u64 },
+    /// a strict cassette has no response recorded for the request with this query
+    NotRecorded(String),
+}
+
+pub type TempmailResult<T> = Result<T, TempmailError>;
+
+impl TempmailError {
+    /// status code of the failed response, if there was one
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            TempmailError::Transport(err) => err.status(),
+            TempmailError::Status { status, .. } => Some(*status),
+            TempmailError::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS),
+            _ => None,
+        }
+    }
+
+    /// how long the api asked to wait before trying again, if it did
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            TempmailError::Status { retry_after, .. } | TempmailError::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// whether the error is likely to go away by itself: connection failures and timeouts,
+    /// 5xx answers, rate limiting and empty bodies, which the api sends under load
+    pub fn is_transient(&self) -> bool {
+        match self {
+            TempmailError::Transport(err) => err.is_timeout() || err.is_connect() || err.is_body(),
+            TempmailError::Status { status, .. } => status.is_server_error(),
+            TempmailError::RateLimited { .. } => true,
+            TempmailError::Decode { body, .. } => body.trim().is_empty(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn decode<M>(message: M, body: &str) -> Self
+    where
+        M: Display,
+    {
+        TempmailError::Decode { message: message.to_string(), body: snippet(body) }
+    }
+
+    /// turns a non-success response into the matching error
+    pub(crate) fn from_response(status: StatusCode, headers: &HeaderMap, body: &str) -> Self {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => TempmailError::RateLimited { retry_after: retry_after(headers) },
+            StatusCode::NOT_FOUND => TempmailError::NotFound,
+            _ => TempmailError::Status { status, body: snippet(body), retry_after: retry_after(headers) },
+        }
+    }
+}
+
+impl Display for TempmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TempmailError::Transport(err) => write!(f, "request failed: {}", err),
+            TempmailError::Status { status, body, .. } => write!(f, "api returned {}: {}", status, body),
+            TempmailError::RateLimited { retry_after: Some(after) } => {
+                write!(f, "rate limited, retry after {}s", after.as_secs())
+            }
+            TempmailError::RateLimited { retry_after: None } => f.write_str("rate limited"),
+            TempmailError::Decode { message, body } => {
+                write!(f, "failed to decode api response ({}): {}", message, body)
+            }
+            TempmailError::NotFound => f.write_str("message not found"),
+            TempmailError::InvalidInput(reason) => write!(f, "invalid input: {}", reason),
+            TempmailError::Timeout(after) => write!(f, "timed out after {:?}", after),
+            TempmailError::UnknownDomain(domain) => write!(f, "unknown domain: {}", domain),
+            TempmailError::InvalidAddress(reason) => write!(f, "invalid address: {}", reason),
+            TempmailError::Unsupported(what) => write!(f, "not supported by this provider: {}", what),
+            TempmailError::Unavailable => f.write_str("no healthy provider or domain available"),
+            TempmailError::Io(err) => write!(f, "io error: {}", err),
+            TempmailError::SizeMismatch { expected, actual } => {
+                write!(f, "expected {} bytes, got {}", expected, actual)
+            }
+            TempmailError::NotRecorded(query) => write!(f, "no response recorded for {}", query),
+        }
+    }
+}
+
+impl std::error::Error for TempmailError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TempmailError::Transport(err) => Some(err),
+            TempmailError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for TempmailError {
+    fn from(err: reqwest::Error) -> Self {
+        TempmailError::Transport(err)
+    }
+}
+
+impl From<std::io::Error> for TempmailError {
+    fn from(err: std::io::Error) -> Self {
+        TempmailError::Io(err)
+    }
+}
+
+fn snippet(body: &str) -> String {
+    match body.char_indices().nth(SNIPPET_LEN) {
+        Some((idx, _)) => format!("{}...", &body[..idx]),
+        None => body.to_string(),
+    }
+}
+
+/// parses a `Retry-After` header, given in seconds or as an http date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    // http dates are RFC 2822 ones in GMT, like `Wed, 21 Oct 2015 07:28:00 GMT`
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, RETRY_AFTER},
+        StatusCode,
+    };
+    use std::time::Duration;
+
+    use super::TempmailError;
+
+    fn with_retry_after(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn reads_retry_after_from_429s_and_5xxs() {
+        let err = TempmailError::from_response(StatusCode::TOO_MANY_REQUESTS, &with_retry_after("7"), "");
+        assert!(matches!(err, TempmailError::RateLimited { .. }));
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(7)));
+
+        let err = TempmailError::from_response(StatusCode::SERVICE_UNAVAILABLE, &with_retry_after("120"), "busy");
+        assert_eq!(err.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(120)));
+
+        let err = TempmailError::from_response(StatusCode::SERVICE_UNAVAILABLE, &HeaderMap::new(), "busy");
+        assert_eq!(err.retry_after(), None);
+    }
+
+    #[test]
+    fn reads_retry_after_dates() {
+        let later = chrono::Utc::now() + chrono::Duration::seconds(60);
+        let headers = with_retry_after(&later.format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+        let retry_after = TempmailError::from_response(StatusCode::SERVICE_UNAVAILABLE, &headers, "").retry_after();
+        assert!(retry_after.is_some_and(|after| after > Duration::from_secs(55) && after <= Duration::from_secs(60)));
+
+        let headers = with_retry_after("Wed, 21 Oct 2015 07:28:00 GMT");
+        let retry_after = TempmailError::from_response(StatusCode::SERVICE_UNAVAILABLE, &headers, "").retry_after();
+        assert_eq!(retry_after, Some(Duration::ZERO));
+
+        let headers = with_retry_after("soon");
+        assert_eq!(TempmailError::from_response(StatusCode::SERVICE_UNAVAILABLE, &headers, "").retry_after(), None);
+    }
+}
@@ -0,0 +1,299 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::{html, Message};
+
+/// words that usually come right before a one time code
+const CODE_KEYWORDS: &str = r"(?i)\b(?:code|otp|pin|passcode|one[- ]time password|verification number)\b(?:\s+is)?\s*[:#\-]?\s*";
+
+/// words that mark a link as the one to click to confirm something
+const VERIFY_KEYWORDS: [&str; 11] = [
+    "confirm", "verify", "verification", "activate", "activation", "validate", "magic", "sign in", "sign-in", "log in", "login",
+];
+
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("invalid built-in regex"))
+}
+
+fn keyword_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    regex(&RE, CODE_KEYWORDS)
+}
+
+fn keyword_code_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    regex(&RE, r"(?i)^(?:[0-9A-Z]{3,4}[- ][0-9A-Z]{3,4}|[0-9A-Z]{4,8})\b")
+}
+
+fn bare_code_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    regex(&RE, r"\b(?:\d{3}-\d{3}|\d{4,8})\b")
+}
+
+fn url_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    regex(&RE, r#"https?://[^\s<>"'`]+"#)
+}
+
+impl Message {
+    /// finds one time codes in the text and html bodies
+    ///
+    /// codes right after words like "code" or "otp" come first (these may contain letters, like
+    /// `AB12-CD34`), then bare 4 to 8 digit numbers that don't look like years, dates, prices or
+    /// phone numbers. separators are removed, so `123-456` is returned as `123456`
+    pub fn extract_codes(&self) -> Vec<String> {
+        let mut keyword_codes = Vec::new();
+        let mut bare_codes = Vec::new();
+
+        for text in self.searchable_texts() {
+            for keyword in keyword_re().find_iter(&text) {
+                let Some(code) = keyword_code_re().find(&text[keyword.end()..]) else {
+                    continue;
+                };
+
+                if code.as_str().chars().any(|c| c.is_ascii_digit()) {
+                    keyword_codes.push(strip_separators(code.as_str()));
+                }
+            }
+
+            for code in bare_code_re().find_iter(&text) {
+                if is_standalone(&text, code.start(), code.end())
+                    && !follows_plus(&text[..code.start()])
+                    && !looks_like_year(code.as_str())
+                {
+                    bare_codes.push(strip_separators(code.as_str()));
+                }
+            }
+        }
+
+        dedup(keyword_codes.into_iter().chain(bare_codes))
+    }
+
+    /// every http(s) link in the html body's anchors and the text body, in order, without duplicates
+    pub fn extract_links(&self) -> Vec<String> {
+        let html_links = self
+            .html_body
+            .iter()
+            .flat_map(|body| html::anchors(body))
+            .map(|anchor| anchor.href);
+
+        let text_links = url_re()
+            .find_iter(&self.text_body)
+            .map(|url| trim_url(url.as_str()).to_string())
+            .collect::<Vec<_>>();
+
+        dedup(html_links.chain(text_links).filter(|link| is_http(link)))
+    }
+
+    /// finds the link to click to confirm, verify or activate something, or to log in
+    ///
+    /// links whose anchor text mentions it are preferred over links that only mention it in
+    /// their url. unsubscribe links are skipped, and with a `host_filter` only links to that
+    /// host or its subdomains are considered
+    pub fn find_verification_link(&self, host_filter: Option<&str>) -> Option<String> {
+        let mut candidates: Vec<(String, String)> = self
+            .html_body
+            .iter()
+            .flat_map(|body| html::anchors(body))
+            .map(|anchor| (anchor.href, anchor.text))
+            .collect();
+
+        for line in self.text_body.lines() {
+            for url in url_re().find_iter(line) {
+                candidates.push((trim_url(url.as_str()).to_string(), line.to_string()));
+            }
+        }
+
+        candidates.retain(|(href, _)| {
+            is_http(href)
+                && !href.to_lowercase().contains("unsubscribe")
+                && host_filter.is_none_or(|host| host_matches(href, host))
+        });
+
+        let by_text = candidates
+            .iter()
+            .find(|(_, text)| mentions_verification(text));
+        let by_url = candidates
+            .iter()
+            .find(|(href, _)| mentions_verification(href));
+
+        by_text.or(by_url).map(|(href, _)| href.clone())
+    }
+
+    /// the readable text of the message, rendering the html body when the text body is
+    /// missing or less than half as long as the rendered html
+    ///
+    /// the html is rendered with links as numbered footnotes, lists as bullets or numbers and
+    /// table cells joined with ` | `, leaving out scripts, styles and the document head
+    pub fn rendered_text(&self) -> String {
+        let rendered = self.html_body.as_deref().map(html::to_text).unwrap_or_default();
+        let text = self.text_body.trim();
+
+        if text.chars().count() * 2 < rendered.chars().count() {
+            rendered
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// the text body and the text of the html body, with entities decoded
+    fn searchable_texts(&self) -> Vec<String> {
+        let mut texts = vec![html::decode_entities(&self.text_body)];
+        texts.extend(self.html_body.iter().map(|body| html::text_content(body)));
+        texts
+    }
+}
+
+fn strip_separators(code: &str) -> String {
+    code.chars().filter(|c| !matches!(c, '-' | ' ')).collect()
+}
+
+/// rejects numbers glued to other numbers, like in `2024-01-02`, `1,299.00` or `+15550100`
+fn is_standalone(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().rev().take(2).collect::<Vec<_>>();
+    let after = text[end..].chars().take(2).collect::<Vec<_>>();
+
+    let glued_before = matches!(before.as_slice(), [sep, d, ..] if "-.,:/+".contains(*sep) && d.is_ascii_digit())
+        || matches!(before.as_slice(), ['+' | '$' | '€' | '£' | '#', ..]);
+    let glued_after = matches!(after.as_slice(), [sep, d, ..] if "-.,:/".contains(*sep) && d.is_ascii_digit())
+        || matches!(after.as_slice(), ['%', ..]);
+
+    !glued_before && !glued_after
+}
+
+/// whether the text ends in a phone number's leading groups, like `+1 ` or `+44 (20) `, so
+/// what follows it is the rest of the number
+fn follows_plus(text: &str) -> bool {
+    let mut rest = text;
+
+    loop {
+        let trimmed = rest.trim_end_matches([' ', '-', '(', ')']);
+        let stripped = trimmed.trim_end_matches(|c: char| c.is_ascii_digit());
+
+        if stripped.len() == trimmed.len() {
+            return trimmed.ends_with('+');
+        }
+
+        rest = stripped;
+    }
+}
+
+fn looks_like_year(code: &str) -> bool {
+    code.len() == 4 && matches!(code.parse::<u32>(), Ok(1900..=2099))
+}
+
+fn is_http(link: &str) -> bool {
+    let lower = link.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// drops punctuation that ends the sentence a url is in
+fn trim_url(url: &str) -> &str {
+    url.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '}'])
+}
+
+fn host_matches(link: &str, host: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(link) else {
+        return false;
+    };
+
+    let host = host.trim_start_matches('.').to_ascii_lowercase();
+
+    url.host_str().is_some_and(|link_host| {
+        link_host == host || link_host.ends_with(&format!(".{}", host))
+    })
+}
+
+fn mentions_verification(text: &str) -> bool {
+    let text = text.to_lowercase();
+    VERIFY_KEYWORDS.iter().any(|keyword| text.contains(keyword))
+}
+
+fn dedup<I>(items: I) -> Vec<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut unique = Vec::new();
+
+    for item in items {
+        if !unique.contains(&item) {
+            unique.push(item);
+        }
+    }
+
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::Message;
+
+    fn message(text_body: &str, html_body: Option<&str>) -> Message {
+        Message {
+            id: 1,
+            from: "alice@example.com".to_string(),
+            subject: "your code".to_string(),
+            timestamp: Utc::now(),
+            attachments: Vec::new(),
+            body: html_body.unwrap_or(text_body).to_string(),
+            text_body: text_body.to_string(),
+            html_body: html_body.map(str::to_string),
+        }
+    }
+
+    fn codes(text_body: &str) -> Vec<String> {
+        message(text_body, None).extract_codes()
+    }
+
+    #[test]
+    fn finds_codes_after_keywords_first() {
+        assert_eq!(codes("Use 4821 or your code: AB12-CD34"), ["AB12CD34", "4821"]);
+        assert_eq!(codes("Your verification code is 123-456."), ["123456"]);
+        assert_eq!(codes("PIN #9921"), ["9921"]);
+    }
+
+    #[test]
+    fn keywords_and_codes_ignore_case() {
+        assert_eq!(codes("OTP: ab12cd"), ["ab12cd"]);
+        assert_eq!(codes("your code is x7k9p2"), ["x7k9p2"]);
+        assert!(codes("your code is below").is_empty());
+    }
+
+    #[test]
+    fn skips_years_dates_prices_and_phone_numbers() {
+        assert!(codes("(c) 2024, sent 2024-01-02 for $1299 or 1,299.00, 15% off").is_empty());
+        assert!(codes("call +15550100 or +1 5550100").is_empty());
+        assert!(codes("call +1 555 0100 or +44 (20) 79460958").is_empty());
+        assert!(codes("code: +1 5550100").is_empty());
+        assert_eq!(codes("call +1 5550100, your code is 4821"), ["4821"]);
+    }
+
+    #[test]
+    fn searches_the_html_body() {
+        let msg = message("", Some("<p>Your code is <b>738&#50;91</b></p>"));
+        assert_eq!(msg.extract_codes(), ["738291"]);
+    }
+
+    #[test]
+    fn finds_links_and_the_verification_link() {
+        let html = r#"<a href="https://example.com/unsubscribe?u=1">Verify your unsubscription</a>
+            <a href="https://example.com/home">Home</a>
+            <a href="https://app.example.com/t/abc">Confirm your email</a>"#;
+        let msg = message("or open https://other.com/verify?t=abc.", Some(html));
+
+        assert_eq!(
+            msg.extract_links(),
+            [
+                "https://example.com/unsubscribe?u=1",
+                "https://example.com/home",
+                "https://app.example.com/t/abc",
+                "https://other.com/verify?t=abc",
+            ]
+        );
+        assert_eq!(msg.find_verification_link(None).as_deref(), Some("https://app.example.com/t/abc"));
+        assert_eq!(msg.find_verification_link(Some("other.com")).as_deref(), Some("https://other.com/verify?t=abc"));
+        assert_eq!(msg.find_verification_link(Some("nowhere.com")), None);
+    }
+}
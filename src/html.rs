@@ -0,0 +1,599 @@
+//! Just enough html handling to pick mail bodies apart, this is not a spec compliant parser
+
+/// A piece of an html document
+#[derive(Debug, PartialEq)]
+pub(crate) enum Token {
+    /// an opening tag, with a lowercase name and decoded attribute values
+    Start { name: String, attrs: Vec<(String, String)>, self_closing: bool },
+    /// a closing tag, with a lowercase name
+    End { name: String },
+    /// decoded text between tags
+    Text(String),
+}
+
+/// A link, with the text it's shown as
+#[derive(Debug)]
+pub(crate) struct Anchor {
+    pub href: String,
+    pub text: String,
+}
+
+/// tags whose content isn't html and is never shown
+const RAW_TEXT_TAGS: [&str; 2] = ["script", "style"];
+
+impl Token {
+    pub fn attr(&self, attr: &str) -> Option<&str> {
+        match self {
+            Token::Start { attrs, .. } => attrs
+                .iter()
+                .find(|(name, _)| name == attr)
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// splits html into tags and text, dropping comments, doctypes and script/style content
+pub(crate) fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(lt) = rest.find('<') else {
+            tokens.push(Token::Text(decode_entities(rest)));
+            break;
+        };
+
+        if lt > 0 {
+            tokens.push(Token::Text(decode_entities(&rest[..lt])));
+        }
+        rest = &rest[lt..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let starts_tag = rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!' || c == '?');
+        let Some(gt) = tag_end(rest).filter(|_| starts_tag) else {
+            tokens.push(Token::Text("<".to_string()));
+            rest = &rest[1..];
+            continue;
+        };
+
+        let tag = &rest[1..gt];
+        rest = &rest[gt + 1..];
+
+        if tag.starts_with('!') || tag.starts_with('?') {
+            continue;
+        }
+
+        if let Some(name) = tag.strip_prefix('/') {
+            tokens.push(Token::End { name: name.trim().to_ascii_lowercase() });
+            continue;
+        }
+
+        let token = parse_tag(tag);
+
+        if let Token::Start { name, self_closing: false, .. } = &token {
+            if RAW_TEXT_TAGS.contains(&name.as_str()) {
+                let name = name.clone();
+                rest = skip_raw_text(rest, &name);
+                tokens.push(token);
+                tokens.push(Token::End { name });
+                continue;
+            }
+        }
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// finds the `>` closing a tag, ignoring the ones inside quoted attribute values
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+
+    for (idx, c) in tag.char_indices().skip(1) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(idx),
+            (None, '<') => return None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn skip_raw_text<'a>(html: &'a str, name: &str) -> &'a str {
+    let closing = format!("</{}", name);
+
+    match html.to_ascii_lowercase().find(&closing) {
+        Some(start) => html[start..].find('>').map_or("", |end| &html[start + end + 1..]),
+        None => "",
+    }
+}
+
+fn parse_tag(tag: &str) -> Token {
+    let self_closing = tag.ends_with('/');
+    let tag = tag.trim_end_matches('/');
+
+    let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+    let name = tag[..name_end].to_ascii_lowercase();
+
+    let mut attrs = Vec::new();
+    let mut rest = tag[name_end..].trim_start();
+
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_ascii_lowercase();
+        rest = rest[key_end..].trim_start();
+
+        let mut value = String::new();
+
+        if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start();
+
+            let (raw, remaining) = match after_eq.chars().next() {
+                Some(q @ ('"' | '\'')) => {
+                    let inner = &after_eq[1..];
+                    match inner.find(q) {
+                        Some(end) => (&inner[..end], &inner[end + 1..]),
+                        None => (inner, ""),
+                    }
+                }
+                _ => {
+                    let end = after_eq.find(char::is_whitespace).unwrap_or(after_eq.len());
+                    (&after_eq[..end], &after_eq[end..])
+                }
+            };
+
+            value = decode_entities(raw);
+            rest = remaining.trim_start();
+        }
+
+        if !key.is_empty() {
+            attrs.push((key, value));
+        }
+    }
+
+    Token::Start { name, attrs, self_closing }
+}
+
+/// the text of a document, with every tag replaced by a space
+pub(crate) fn text_content(html: &str) -> String {
+    tokenize(html)
+        .into_iter()
+        .map(|token| match token {
+            Token::Text(text) => text,
+            _ => " ".to_string(),
+        })
+        .collect()
+}
+
+/// every `<a href>` of a document, with its whitespace collapsed text
+pub(crate) fn anchors(html: &str) -> Vec<Anchor> {
+    let mut anchors = Vec::new();
+    let mut open: Option<Anchor> = None;
+
+    for token in tokenize(html) {
+        match token {
+            Token::Start { ref name, .. } if name == "a" => {
+                anchors.extend(open.take());
+                open = token.attr("href").map(|href| Anchor { href: href.trim().to_string(), text: String::new() });
+            }
+            Token::Start { ref name, .. } if name == "img" => {
+                if let (Some(anchor), Some(alt)) = (open.as_mut(), token.attr("alt")) {
+                    anchor.text.push_str(alt);
+                }
+            }
+            Token::End { name } if name == "a" => anchors.extend(open.take()),
+            Token::Text(text) => {
+                if let Some(anchor) = open.as_mut() {
+                    anchor.text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    anchors.extend(open);
+
+    for anchor in &mut anchors {
+        anchor.text = collapse_whitespace(&anchor.text);
+    }
+
+    anchors
+}
+
+/// tags that start on a new line
+const BLOCK_TAGS: [&str; 18] = [
+    "address", "article", "aside", "blockquote", "center", "dd", "div", "dl", "dt", "footer", "form", "header",
+    "main", "nav", "section", "table", "tbody", "ul",
+];
+
+/// tags that are separated from what's around them by a blank line
+const PARAGRAPH_TAGS: [&str; 9] = ["p", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "ol"];
+
+/// tags whose content is never shown
+const HIDDEN_TAGS: [&str; 3] = ["head", "title", "template"];
+
+/// Renders html into readable plain text
+///
+/// lists get bullets or numbers, table cells are joined with ` | `, and links are turned
+/// into numbered footnotes listed at the end
+#[derive(Default)]
+struct TextRenderer {
+    out: String,
+    pending_newlines: usize,
+    pending_space: bool,
+    /// `None` for unordered lists, the next number for ordered ones
+    lists: Vec<Option<usize>>,
+    cells_in_row: usize,
+    pre_depth: usize,
+    hidden_depth: usize,
+    /// href of the link being rendered and where its text starts in `out`
+    link: Option<(String, usize)>,
+    footnotes: Vec<String>,
+}
+
+impl TextRenderer {
+    fn render(mut self, html: &str) -> String {
+        for token in tokenize(html) {
+            match token {
+                Token::Text(text) if self.hidden_depth == 0 => self.text(&text),
+                Token::Text(_) => {}
+                Token::Start { ref name, self_closing, .. } => {
+                    let name = name.clone();
+                    self.start(&name, &token);
+
+                    if self_closing {
+                        self.end(&name);
+                    }
+                }
+                Token::End { name } => self.end(&name),
+            }
+        }
+
+        let mut text = self.out.trim().to_string();
+
+        if !self.footnotes.is_empty() {
+            text.push_str("\n\n");
+
+            for (idx, href) in self.footnotes.iter().enumerate() {
+                text.push_str(&format!("[{}] {}\n", idx + 1, href));
+            }
+        }
+
+        text.trim_end().to_string()
+    }
+
+    fn start(&mut self, name: &str, token: &Token) {
+        if HIDDEN_TAGS.contains(&name) {
+            self.hidden_depth += 1;
+            return;
+        }
+
+        match name {
+            "br" => self.line_break(),
+            "pre" => {
+                self.newlines(2);
+                self.pre_depth += 1;
+            }
+            "li" => {
+                self.newlines(1);
+
+                let depth = self.lists.len().max(1);
+                let marker = match self.lists.last_mut() {
+                    Some(Some(next)) => {
+                        *next += 1;
+                        format!("{}. ", *next - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+
+                self.raw(&format!("{}{}", "  ".repeat(depth - 1), marker));
+            }
+            "ul" => self.lists.push(None),
+            "ol" => self.lists.push(Some(1)),
+            "tr" => {
+                self.newlines(1);
+                self.cells_in_row = 0;
+            }
+            "td" | "th" => {
+                if self.cells_in_row > 0 {
+                    self.raw(" | ");
+                }
+                self.cells_in_row += 1;
+            }
+            "img" => {
+                if let Some(alt) = token.attr("alt").filter(|alt| !alt.trim().is_empty()) {
+                    self.text(alt);
+                }
+            }
+            "a" => {
+                self.link = token
+                    .attr("href")
+                    .map(str::trim)
+                    .filter(|href| !href.is_empty() && !href.starts_with('#') && !href.starts_with("javascript:"))
+                    .map(|href| (href.to_string(), self.out.len()));
+            }
+            _ => {}
+        }
+
+        if name == "ul" || name == "ol" {
+            self.newlines(if self.lists.len() > 1 { 1 } else { 2 });
+        } else if BLOCK_TAGS.contains(&name) {
+            self.newlines(1);
+        } else if PARAGRAPH_TAGS.contains(&name) {
+            self.newlines(2);
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        if HIDDEN_TAGS.contains(&name) {
+            self.hidden_depth = self.hidden_depth.saturating_sub(1);
+            return;
+        }
+
+        match name {
+            "pre" => self.pre_depth = self.pre_depth.saturating_sub(1),
+            "ul" | "ol" => {
+                self.lists.pop();
+            }
+            "a" => self.end_link(),
+            _ => {}
+        }
+
+        if PARAGRAPH_TAGS.contains(&name) || name == "pre" || (self.lists.is_empty() && name == "ul") {
+            self.newlines(2);
+        } else if BLOCK_TAGS.contains(&name) || name == "li" || name == "tr" {
+            self.newlines(1);
+        }
+    }
+
+    fn end_link(&mut self) {
+        let Some((href, start)) = self.link.take() else {
+            return;
+        };
+
+        let text = self.out.get(start..).unwrap_or_default().trim();
+        let shown = href.strip_prefix("mailto:").unwrap_or(&href);
+
+        if text == shown || text == href {
+            return;
+        }
+
+        let number = match self.footnotes.iter().position(|footnote| *footnote == href) {
+            Some(idx) => idx + 1,
+            None => {
+                self.footnotes.push(href);
+                self.footnotes.len()
+            }
+        };
+
+        let footnote = format!("[{}]", number);
+        if text.is_empty() {
+            self.raw(&footnote);
+        } else {
+            self.raw(&format!(" {}", footnote));
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.pre_depth > 0 {
+            self.flush();
+            self.out.push_str(text);
+            return;
+        }
+
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.pending_space = true;
+                continue;
+            }
+
+            let at_line_start = self.out.is_empty() || self.out.ends_with('\n');
+            self.flush();
+
+            if self.pending_space && !at_line_start && self.pending_newlines == 0 && !self.out.ends_with(' ') {
+                self.out.push(' ');
+            }
+
+            self.pending_space = false;
+            self.out.push(c);
+        }
+    }
+
+    /// pushes text as is, without collapsing its whitespace
+    fn raw(&mut self, text: &str) {
+        self.flush();
+        self.out.push_str(text);
+        self.pending_space = false;
+    }
+
+    /// makes sure the next text starts after at least `count` line breaks
+    fn newlines(&mut self, count: usize) {
+        if !self.out.is_empty() {
+            self.pending_newlines = self.pending_newlines.max(count);
+        }
+        self.pending_space = false;
+    }
+
+    fn line_break(&mut self) {
+        self.pending_newlines = (self.pending_newlines + 1).min(2);
+        self.pending_space = false;
+    }
+
+    fn flush(&mut self) {
+        if self.pending_newlines == 0 {
+            return;
+        }
+
+        let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(trimmed);
+
+        let existing = self.out.len() - self.out.trim_end_matches('\n').len();
+        for _ in existing..self.pending_newlines {
+            self.out.push('\n');
+        }
+
+        self.pending_newlines = 0;
+    }
+}
+
+/// renders a document as readable plain text
+pub(crate) fn to_text(html: &str) -> String {
+    TextRenderer::default().render(html)
+}
+
+pub(crate) fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// decodes named and numeric character references, leaving unknown ones alone
+pub(crate) fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let reference = rest[1..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '#')
+            .map_or(&rest[1..], |end| &rest[1..end + 1]);
+
+        match decode_reference(reference) {
+            Some(c) => {
+                if let Some(c) = c {
+                    decoded.push(c);
+                }
+                rest = &rest[reference.len() + 1..];
+                rest = rest.strip_prefix(';').unwrap_or(rest);
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// `Some(None)` for references that decode to nothing, like zero width joiners
+fn decode_reference(reference: &str) -> Option<Option<char>> {
+    if let Some(num) = reference.strip_prefix('#') {
+        let code = match num.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+
+        return Some(char::from_u32(code).filter(|c| !matches!(c, '\u{200b}'..='\u{200d}' | '\u{feff}' | '\u{ad}')));
+    }
+
+    let c = match reference {
+        "amp" | "AMP" => '&',
+        "lt" | "LT" => '<',
+        "gt" | "GT" => '>',
+        "quot" | "QUOT" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "bull" => '•',
+        "middot" => '·',
+        "euro" => '€',
+        "pound" => '£',
+        "zwnj" | "zwj" | "shy" => return Some(None),
+        _ => return None,
+    };
+
+    Some(Some(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{anchors, decode_entities, text_content, to_text, tokenize, Token};
+
+    #[test]
+    fn tolerates_unclosed_and_broken_tags() {
+        assert_eq!(to_text("<div><p>first<p>second"), "first\n\nsecond");
+        assert_eq!(to_text("a < b and c<d"), "a < b and c<d");
+        assert_eq!(to_text("<p>cut off <b class=\"x"), "cut off <b class=\"x");
+        assert_eq!(to_text("<p title='a > b'>quoted</p>"), "quoted");
+
+        let tokens = tokenize("<IMG SRC=x.png ALT=\"hi\"/><br>");
+        assert_eq!(tokens[0].attr("src"), Some("x.png"));
+        assert_eq!(tokens[0].attr("alt"), Some("hi"));
+        assert!(matches!(&tokens[1], Token::Start { name, self_closing: false, .. } if name == "br"));
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(decode_entities("caf&eacute; &amp; &lt;b&gt; &#233;&#x e9; &#x1F600;"), "caf&eacute; & <b> é&#x e9; 😀");
+        assert_eq!(decode_entities("AT&T &copy 2024 &nbsp;"), "AT&T © 2024 \u{a0}");
+        assert_eq!(decode_entities("in&#8203;visible&zwnj;"), "invisible");
+        assert_eq!(tokenize("<a href=\"/x?a=1&amp;b=2\">")[0].attr("href"), Some("/x?a=1&b=2"));
+    }
+
+    #[test]
+    fn strips_scripts_styles_and_the_head() {
+        let html = "<html><head><title>Title</title><style>p { color: red }</style></head>\
+            <body><script>if (a < b) { alert('<p>') }</SCRIPT>shown<!-- hidden --> text</body></html>";
+        assert_eq!(to_text(html), "shown text");
+        assert_eq!(text_content("<script>x</script>a<b>b</b>").split_whitespace().collect::<Vec<_>>(), ["a", "b"]);
+    }
+
+    #[test]
+    fn renders_lists_and_tables() {
+        let html = "<p>Steps:</p><ol><li>one</li><li>two<ul><li>nested</li></ul></li></ol>\
+            <ul><li>bullet</li></ul>\
+            <table><tr><th>Item</th><th>Price</th></tr><tr><td>Tea</td><td>2</td></tr></table>";
+        assert_eq!(
+            to_text(html),
+            "Steps:\n\n1. one\n2. two\n  - nested\n\n- bullet\n\nItem | Price\nTea | 2"
+        );
+    }
+
+    #[test]
+    fn turns_links_into_footnotes() {
+        let html = "<p>Please <a href=\"https://example.com/verify\">verify</a> or \
+            <a href=\"https://example.com\">https://example.com</a>, \
+            <a href=\"mailto:help@example.com\">help@example.com</a>, \
+            <a href=\"#top\">top</a>. <a href=\"https://example.com/verify\">Again</a>\
+            <a href=\"https://example.com/logo\"><img src=\"logo.png\"></a></p>";
+        assert_eq!(
+            to_text(html),
+            "Please verify [1] or https://example.com, help@example.com, top. Again [1][2]\n\n\
+            [1] https://example.com/verify\n\
+            [2] https://example.com/logo"
+        );
+
+        let links = anchors("<a href=\" /a \">A <img alt=\"logo\">\n link</a><a>no href</a><a href=/b>B");
+        let links: Vec<(&str, &str)> = links.iter().map(|link| (link.href.as_str(), link.text.as_str())).collect();
+        assert_eq!(links, [("/a", "A logo link"), ("/b", "B")]);
+    }
+}
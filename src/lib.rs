@@ -1,17 +1,50 @@
 use chrono::prelude::*;
-use serde::{Deserialize, Deserializer};
-use std::{fmt::Display, future::IntoFuture};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt::Display, path::{Path, PathBuf}, str::FromStr, sync::Arc};
 use rand::{thread_rng, Rng};
+use tokio::io::AsyncWrite;
+
+pub mod analysis;
+pub mod auth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod bundle;
+mod cassette;
+mod client;
+mod error;
+mod extract;
+mod html;
+pub mod mime;
+mod poll;
+pub mod provider;
+mod retry;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+pub use bundle::{AttachmentBundle, DownloadedAttachment, DEFAULT_DOWNLOAD_CONCURRENCY};
+pub use cassette::{Cassette, CassetteMode};
+pub use client::{TempmailClient, TempmailClientBuilder};
+pub use error::{TempmailError, TempmailResult};
+pub use mime::MessageSource;
+pub use poll::DEFAULT_POLL_INTERVAL;
+pub use provider::MailProvider;
+pub use retry::RetryPolicy;
+
+use provider::OneSecMail;
 
 /// Represents an attachment sent in an email message
-#[derive(Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Attachment {
     pub filename: String,
+    #[serde(alias = "contentType")]
     pub content_type: String,
     pub size: usize,
 }
 
 /// Represents an email message
+///
+/// serializes with the timestamp as rfc 3339, and deserializes from both that and the api's format
+#[derive(Clone, Debug, Serialize)]
 pub struct Message {
     pub id: usize,
     pub from: String,
@@ -23,6 +56,7 @@ pub struct Message {
     pub html_body: Option<String>, // html-only content of the body
 }
 
+#[derive(Clone, Debug, Serialize)]
 pub struct RawMessage {
     pub id: usize,
     pub from: String,
@@ -35,10 +69,13 @@ pub struct MessageWrapper {
     id: usize,
     from: String,
     subject: String,
+    #[serde(alias = "timestamp")]
     date: String,
     attachments: Vec<Attachment>,
     body: String,
+    #[serde(alias = "textBody")]
     text_body: String,
+    #[serde(alias = "htmlBody")]
     html_body: Option<String>,
 }
 
@@ -47,11 +84,13 @@ pub struct RawMessageWrapper {
     id: usize,
     from: String,
     subject: String,
+    #[serde(alias = "timestamp")]
     date: String,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub enum Domain {
+    #[default]
     SecMailCom,
     SecMailOrg,
     YoggmCom,
@@ -59,16 +98,21 @@ pub enum Domain {
     XojxeCom,
     SecMailNet,
     WwjmpCom,
+    /// any domain the provider serves that isn't one of the above
+    Custom(String),
 }
 
+/// Represents an inbox
+///
+/// only the address is (de)serialized, deserialized inboxes talk to 1secmail through the default client
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Tempmail {
     pub username: String,
     pub domain: Domain,
+    #[serde(skip, default = "default_provider")]
+    provider: Arc<dyn MailProvider>,
 }
 
-pub type TempmailError = reqwest::Error;
-pub type TempmailResult<T> = Result<T, TempmailError>;
-
 impl<'de> Deserialize<'de> for Message {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where 
@@ -76,26 +120,39 @@ impl<'de> Deserialize<'de> for Message {
     {
         let wrapper: MessageWrapper = Deserialize::deserialize(deserializer)?;
         
-        let timestamp = NaiveDateTime::parse_from_str(&wrapper.date, "%Y-%m-%d %H:%M:%S")
-            .map(|ndt| DateTime::<Utc>::from_utc(ndt, Utc))
-            .map_err(serde::de::Error::custom)?;
+        let timestamp = parse_timestamp(&wrapper.date).map_err(serde::de::Error::custom)?;
         
-        Ok(Message { id: wrapper.id, from: wrapper.from, subject: wrapper.subject, timestamp: timestamp, attachments: wrapper.attachments, body: wrapper.body, text_body: wrapper.text_body, html_body: wrapper.html_body })
+        Ok(Message { id: wrapper.id, from: wrapper.from, subject: wrapper.subject, timestamp, attachments: wrapper.attachments, body: wrapper.body, text_body: wrapper.text_body, html_body: wrapper.html_body })
     }
 }
 
-/// I DONT CARE ABOUT USING DEPRECATED FUNCTIONS !!!!!! >:3
 impl<'de> Deserialize<'de> for RawMessage  {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where
             D: Deserializer<'de> {
         let wrapper: RawMessageWrapper = Deserialize::deserialize(deserializer)?;
 
-        let timestamp = NaiveDateTime::parse_from_str(&wrapper.date, "%Y-%m-%d %H:%M:%S")
-            .map(|ndt| DateTime::<Utc>::from_utc(ndt, Utc))
-            .map_err(serde::de::Error::custom)?;
+        let timestamp = parse_timestamp(&wrapper.date).map_err(serde::de::Error::custom)?;
         
-        Ok(RawMessage { id: wrapper.id, from: wrapper.from, subject: wrapper.subject, timestamp: timestamp })
+        Ok(RawMessage { id: wrapper.id, from: wrapper.from, subject: wrapper.subject, timestamp })
+    }
+}
+
+/// parses the api's `%Y-%m-%d %H:%M:%S` dates as well as rfc 3339 ones
+fn parse_timestamp(date: &str) -> chrono::ParseResult<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S")
+        .map(|ndt| ndt.and_utc())
+        .or_else(|_| DateTime::parse_from_rfc3339(date).map(|dt| dt.with_timezone(&Utc)))
+}
+
+impl Message {
+    /// the api sends an empty string when there's no html part
+    pub(crate) fn normalized(mut self) -> Self {
+        if self.html_body.as_deref() == Some("") {
+            self.html_body = None;
+        }
+
+        self
     }
 }
 
@@ -115,8 +172,68 @@ impl Domain {
         Domain::YoggmCom,
     ];
 
+    /// picks one of the known domains, without asking the api
     pub fn random() -> Self {
-        Self::DOMAINS[(random_rng() * Self::DOMAINS.len() as f64).round() as usize].clone()
+        Self::DOMAINS[(random_rng() * Self::DOMAINS.len() as f64) as usize].clone()
+    }
+
+    /// maps a domain name to its known variant, falling back to [`Domain::Custom`]
+    pub fn from_name<N>(name: N) -> Self
+    where
+        N: AsRef<str>,
+    {
+        let name = name.as_ref().trim();
+
+        Self::DOMAINS
+            .iter()
+            .find(|domain| domain.to_string().eq_ignore_ascii_case(name))
+            .cloned()
+            .unwrap_or_else(|| Domain::Custom(name.to_ascii_lowercase()))
+    }
+
+    /// asks the api which domains it currently serves
+    pub async fn fetch_available() -> TempmailResult<Vec<Domain>> {
+        TempmailClient::default().get_domains().await
+    }
+
+    /// picks one domain of a live domain list, an empty one meaning the service can't take mail
+    pub(crate) fn pick(domains: Vec<Domain>) -> TempmailResult<Self> {
+        let idx = (random_rng() * domains.len() as f64) as usize;
+
+        domains.into_iter().nth(idx).ok_or(TempmailError::Unavailable)
+    }
+}
+
+impl FromStr for Domain {
+    type Err = TempmailError;
+
+    /// parses one of the known domains, anything else is rejected with
+    /// [`TempmailError::UnknownDomain`] (use [`Domain::from_name`] to accept any domain)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Domain::from_name(s) {
+            Domain::Custom(name) => Err(TempmailError::UnknownDomain(name)),
+            domain => Ok(domain),
+        }
+    }
+}
+
+impl Serialize for Domain {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Domain {
+    /// accepts any domain name, like [`Domain::from_name`]
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+        Ok(Domain::from_name(name))
     }
 }
 
@@ -130,31 +247,12 @@ impl Display for Domain {
             Domain::EsiixCom => f.write_str("esiix.com"),
             Domain::XojxeCom => f.write_str("xojxe.com"),
             Domain::YoggmCom => f.write_str("yoggm.com"),
+            Domain::Custom(name) => f.write_str(name),
         }
     }
 }
 
-impl Default for Domain {
-    fn default() -> Self {
-        Domain::SecMailCom
-    }
-}
-
-const API_URL: &str = "https://www.1secmail.com/api/v1/";
-
-/// function to do a json get req and deserialize it
-async fn reqjson<T, R>(query: T) -> TempmailResult<R>
-where
-    T: AsRef<str>,
-    R: for<'de> Deserialize<'de>,
-{
-    reqwest::get(format!("{}?{}", API_URL, query.as_ref()))
-        .await?
-        .json()
-        .await
-}
-
-fn random_string(length: usize) -> String {
+pub(crate) fn random_string(length: usize) -> String {
     let mut random_string = String::with_capacity(length);
 
     let chars: Vec<char> = "abcdefghijklmnopqrstuwxyzABCDEFGHIJKLMNOPQRSTUWXYZ0123456789"
@@ -169,24 +267,106 @@ fn random_string(length: usize) -> String {
     random_string
 }
 
+/// splits a `username@domain` address, checking both halves
+pub(crate) fn parse_address(address: &str) -> TempmailResult<(String, Domain)> {
+    let (username, domain) = address
+        .trim()
+        .rsplit_once('@')
+        .ok_or_else(|| TempmailError::InvalidAddress(format!("{} is missing an @", address)))?;
+
+    check_username(username)?;
+    Ok((username.to_string(), domain.parse()?))
+}
+
+/// usernames are the local part of an address, so we stick to what every provider accepts
+pub(crate) fn check_username(username: &str) -> TempmailResult<()> {
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+
+    if username.is_empty() || username.len() > 64 || !valid_chars || username.starts_with('.') || username.ends_with('.') {
+        return Err(TempmailError::InvalidAddress(format!("{} is not a valid username", username)));
+    }
+
+    Ok(())
+}
+
+fn default_provider() -> Arc<dyn MailProvider> {
+    Arc::new(OneSecMail::default())
+}
+
+/// keeps the last path component of a filename and drops the characters that aren't safe in one
+pub(crate) fn sanitize_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*'))
+        .collect();
+    let name = name.trim_matches([' ', '.']);
+
+    if name.is_empty() {
+        "attachment".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+pub(crate) fn random_username() -> String {
+    let len = (10.0 + random_rng() * 40.0).floor() as usize;
+    random_string(len)
+}
+
 impl Tempmail {
     pub fn new<U>(username: U, domain: Option<Domain>) -> Self 
     where
         U: Into<String>
     {
-        Self { username: username.into(), domain: domain.unwrap_or_default() }
+        Self::with_client(TempmailClient::default(), username, domain)
+    }
+
+    /// creates a 1secmail inbox that sends its requests through the given client
+    pub fn with_client<U>(client: TempmailClient, username: U, domain: Option<Domain>) -> Self
+    where
+        U: Into<String>
+    {
+        Self::with_provider(Arc::new(OneSecMail::new(client)), username, domain)
+    }
+
+    /// creates an inbox on the given provider, for an address it already knows about
+    pub fn with_provider<U>(provider: Arc<dyn MailProvider>, username: U, domain: Option<Domain>) -> Self
+    where
+        U: Into<String>
+    {
+        Self { username: username.into(), domain: domain.unwrap_or_default(), provider }
+    }
+
+    /// asks the provider for a brand new address
+    pub async fn create(provider: Arc<dyn MailProvider>) -> TempmailResult<Self> {
+        let (username, domain) = provider.create_address().await?;
+        Ok(Self::with_provider(provider, username, Some(domain)))
     }
 
     pub fn random() -> Self {
-        let len = (10.0 + random_rng() * 40.0).floor() as usize;
-        let username = random_string(len);
-        let domain = Domain::random();
+        TempmailClient::default().random_inbox()
+    }
+
+    /// like [`Tempmail::random`], but picks the domain from the ones the api currently serves
+    pub async fn random_live() -> TempmailResult<Self> {
+        TempmailClient::default().random_live_inbox().await
+    }
+
+    pub fn provider(&self) -> &Arc<dyn MailProvider> {
+        &self.provider
+    }
 
-        Self { username: username, domain: domain }
+    /// the full email address of this inbox
+    pub fn address(&self) -> String {
+        self.to_string()
     }
 
     pub async fn get_raw_messages(&self) -> TempmailResult<Vec<RawMessage>> {
-        reqjson(format!("action=getMessages&login={}&domain={}", self.username, self.domain)).await
+        self.provider.list_messages(&self.username, &self.domain).await
     }
 
     pub async fn get_messages(&self) -> TempmailResult<Vec<Message>> {
@@ -203,15 +383,7 @@ impl Tempmail {
     }
 
     pub async fn read_raw_messsage(&self, raw_msg: &RawMessage) -> TempmailResult<Message> {
-        let mut msg: Message = reqjson(format!("action=readMesage&login={}&domain={}&id={}", self.username, self.domain, raw_msg.id)).await?;
-
-        if let Some(html_body) = msg.html_body.clone() {
-            if html_body.is_empty() {
-                msg.html_body = None;
-            }
-        }
-
-        Ok(msg)
+        self.provider.read_message(&self.username, &self.domain, raw_msg.id).await
     }
 
     /// gets attachment of a msg_id and filename
@@ -219,16 +391,87 @@ impl Tempmail {
     where
         T: AsRef<str>,
     {
-        reqwest::get(format!(
-            "action=download&login={}&domain={}&id={}&file={}",
-            self.username,
-            self.domain,
-            msg_id,
-            filename.as_ref()
-        ))
-        .await?
-        .bytes()
-        .await
-        .map(|b| b.to_vec())
+        self.provider.download_attachment(&self.username, &self.domain, msg_id, filename.as_ref()).await
+    }
+
+    /// streams an attachment into a writer instead of buffering it, returns how many bytes were written
+    pub async fn get_attachment_to<T, W>(&self, msg_id: usize, filename: T, writer: &mut W) -> TempmailResult<u64>
+    where
+        T: AsRef<str>,
+        W: AsyncWrite + Unpin + Send,
+    {
+        self.provider
+            .download_attachment_to(&self.username, &self.domain, msg_id, filename.as_ref(), writer)
+            .await
+    }
+
+    /// streams an attachment into a file in `dir`, returning the path it was saved to
+    ///
+    /// the file is named after the attachment, stripped of any path or character that isn't
+    /// safe in a filename. if the download doesn't have the size the message announced the file
+    /// is removed and a [`TempmailError::SizeMismatch`] is returned
+    pub async fn save_attachment<D>(&self, msg_id: usize, attachment: &Attachment, dir: D) -> TempmailResult<PathBuf>
+    where
+        D: AsRef<Path>,
+    {
+        let path = dir.as_ref().join(sanitize_filename(&attachment.filename));
+        let mut file = tokio::fs::File::create(&path).await?;
+
+        let res = self.get_attachment_to(msg_id, &attachment.filename, &mut file).await;
+        drop(file);
+
+        let res = res.and_then(|written| match written == attachment.size as u64 {
+            true => Ok(path.clone()),
+            false => Err(TempmailError::SizeMismatch { expected: attachment.size, actual: written }),
+        });
+
+        if res.is_err() {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+
+        res
+    }
+
+    /// gets the raw source of a message, with its headers parsed
+    pub async fn get_source(&self, msg_id: usize) -> TempmailResult<MessageSource> {
+        let raw = self.provider.get_source(&self.username, &self.domain, msg_id).await?;
+        Ok(MessageSource::parse(raw))
+    }
+}
+
+impl FromStr for Tempmail {
+    type Err = TempmailError;
+
+    /// parses a `username@domain` address into an inbox using the default client
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (username, domain) = parse_address(s)?;
+        Ok(Self::new(username, Some(domain)))
+    }
+}
+
+impl Display for Tempmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.username, self.domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{provider::MemoryProvider, Domain, Tempmail, TempmailError};
+
+    #[test]
+    fn picks_one_of_the_domains() {
+        let domains = vec![Domain::SecMailCom, Domain::EsiixCom];
+        assert!(domains.contains(&Domain::pick(domains.clone()).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn no_domains_means_unavailable() {
+        let provider = Arc::new(MemoryProvider::new());
+        provider.mailbox().set_domains(Vec::new());
+
+        assert!(matches!(Tempmail::create(provider).await, Err(TempmailError::Unavailable)));
     }
 }
@@ -0,0 +1,716 @@
+//! Raw RFC 822 messages, and a MIME parser turning them into a tree of parts
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use chrono::{DateTime, Utc};
+
+use crate::{Attachment, Message};
+
+/// base64 as found in mail, where padding is often missing or wrong
+const LENIENT_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent).with_decode_allow_trailing_bits(true),
+);
+
+/// how deep multiparts are parsed, anything nested deeper is kept as an opaque leaf
+const MAX_DEPTH: usize = 48;
+
+/// The header fields of a message, in the order they appear
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+/// The raw source of a message, as it was received
+#[derive(Clone, Debug)]
+pub struct MessageSource {
+    raw: Vec<u8>,
+    headers: Headers,
+    body_start: usize,
+}
+
+impl Headers {
+    /// parses a header block, unfolding continuation lines
+    pub fn parse(block: &[u8]) -> Self {
+        let block = String::from_utf8_lossy(block);
+        let mut fields: Vec<(String, String)> = Vec::new();
+
+        for line in block.lines() {
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                continue;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                fields.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        Self { fields }
+    }
+
+    /// the first value of a header, names are case insensitive
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// every value of a header, like all the `Received` ones
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// the first value of a header with its RFC 2047 encoded words decoded
+    pub fn get_decoded(&self, name: &str) -> Option<String> {
+        self.get(name).map(decode_words)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl MessageSource {
+    pub fn parse(raw: Vec<u8>) -> Self {
+        let (header_end, body_start) = split_header(&raw);
+        let headers = Headers::parse(&raw[..header_end]);
+
+        Self { raw, headers, body_start }
+    }
+
+    /// the whole message, headers included
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// shorthand for `headers().get(name)`
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// the still encoded body, everything after the blank line ending the headers
+    pub fn body(&self) -> &[u8] {
+        &self.raw[self.body_start..]
+    }
+
+    pub fn into_raw(self) -> Vec<u8> {
+        self.raw
+    }
+
+    /// parses the message into its tree of MIME parts
+    pub fn mime(&self) -> MimePart {
+        MimePart::parse(&self.raw)
+    }
+}
+
+/// A node of a MIME message, the root being the message itself
+///
+/// multipart nodes have `parts` and an empty `body`, the others have their body with the
+/// transfer encoding (base64, quoted-printable) already undone. multiparts nested more than
+/// 48 levels deep aren't split, and keep their raw body instead
+#[derive(Clone, Debug)]
+pub struct MimePart {
+    pub headers: Headers,
+    /// lowercase `type/subtype`, `text/plain` when missing
+    pub content_type: String,
+    pub parts: Vec<MimePart>,
+    pub body: Vec<u8>,
+    params: Vec<(String, String)>,
+}
+
+impl MimePart {
+    pub fn parse(raw: &[u8]) -> Self {
+        Self::parse_nested(raw, 0)
+    }
+
+    fn parse_nested(raw: &[u8], depth: usize) -> Self {
+        let (header_end, body_start) = split_header(raw);
+        let headers = Headers::parse(&raw[..header_end]);
+        let body = &raw[body_start..];
+
+        let (content_type, params) = parse_content_type(headers.get("Content-Type").unwrap_or("text/plain"));
+        let boundary = params
+            .iter()
+            .find(|(name, _)| name == "boundary")
+            .map(|(_, value)| value.clone());
+
+        let mut part = Self { headers, content_type, parts: Vec::new(), body: Vec::new(), params };
+
+        match boundary {
+            Some(boundary) if part.is_multipart() && depth < MAX_DEPTH => {
+                part.parts = split_multipart(body, &boundary)
+                    .into_iter()
+                    .map(|raw| MimePart::parse_nested(raw, depth + 1))
+                    .collect();
+            }
+            _ if part.is_multipart() => part.body = body.to_vec(),
+            _ => {
+                let encoding = part.headers.get("Content-Transfer-Encoding").unwrap_or_default();
+                part.body = decode_transfer(body, encoding);
+            }
+        }
+
+        part
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.content_type.starts_with("multipart/")
+    }
+
+    /// a parameter of the `Content-Type` header, like `charset`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// `attachment` or `inline`, lowercase
+    pub fn disposition(&self) -> Option<String> {
+        self.headers
+            .get("Content-Disposition")
+            .map(|value| parse_content_type(value).0)
+    }
+
+    /// the filename from `Content-Disposition`, or else the `name` of `Content-Type`
+    pub fn filename(&self) -> Option<String> {
+        let disposition_params = self
+            .headers
+            .get("Content-Disposition")
+            .map(|value| parse_content_type(value).1)
+            .unwrap_or_default();
+
+        disposition_params
+            .iter()
+            .find(|(name, _)| name == "filename")
+            .map(|(_, value)| value.as_str())
+            .or_else(|| self.param("name"))
+            .map(decode_words)
+    }
+
+    /// the `Content-ID` without its angle brackets, used by inline images
+    pub fn content_id(&self) -> Option<&str> {
+        self.headers
+            .get("Content-ID")
+            .map(|id| id.trim_start_matches('<').trim_end_matches('>'))
+    }
+
+    /// the name the part goes by as an [`Attachment`]: its filename, else its content id
+    pub fn attachment_name(&self) -> String {
+        self.filename()
+            .or_else(|| self.content_id().map(str::to_string))
+            .unwrap_or_else(|| "attachment".to_string())
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        if self.is_multipart() {
+            return false;
+        }
+
+        match self.disposition().as_deref() {
+            Some("attachment") => true,
+            _ => self.filename().is_some() || !self.content_type.starts_with("text/"),
+        }
+    }
+
+    /// the body of a `text/*` part, decoded from its charset
+    pub fn text(&self) -> Option<String> {
+        if !self.content_type.starts_with("text/") || self.is_multipart() {
+            return None;
+        }
+
+        let charset = self.param("charset").unwrap_or("utf-8");
+        let encoding = encoding_rs::Encoding::for_label(charset.as_bytes()).unwrap_or(encoding_rs::UTF_8);
+
+        Some(encoding.decode(&self.body).0.into_owned())
+    }
+
+    /// this part and every part below it, depth first
+    pub fn walk(&self) -> Vec<&MimePart> {
+        let mut parts = vec![self];
+        for part in &self.parts {
+            parts.extend(part.walk());
+        }
+        parts
+    }
+
+    /// every leaf part that's an attachment, inline images included
+    pub fn attachments(&self) -> Vec<&MimePart> {
+        self.walk().into_iter().filter(|part| part.is_attachment()).collect()
+    }
+
+    /// the first text part of the given subtype that isn't an attachment
+    pub fn body_text(&self, subtype: &str) -> Option<String> {
+        let content_type = format!("text/{}", subtype);
+
+        self.walk()
+            .into_iter()
+            .find(|part| part.content_type == content_type && !part.is_attachment())
+            .and_then(MimePart::text)
+    }
+
+    /// projects the message onto the crate's [`Message`]
+    ///
+    /// `from` is the bare address of the `From` header and the timestamp comes from the `Date`
+    /// header, falling back to now when it's missing or invalid
+    pub fn to_message(&self, id: usize) -> Message {
+        let from = self.headers.get_decoded("From").unwrap_or_default();
+        let timestamp = self
+            .headers
+            .get("Date")
+            .and_then(|date| DateTime::parse_from_rfc2822(date.trim()).ok())
+            .map_or_else(Utc::now, |date| date.with_timezone(&Utc));
+
+        let text_body = self.body_text("plain").unwrap_or_default();
+        let html_body = self.body_text("html");
+
+        let attachments = self
+            .attachments()
+            .into_iter()
+            .map(|part| Attachment {
+                filename: part.attachment_name(),
+                content_type: part.content_type.clone(),
+                size: part.body.len(),
+            })
+            .collect();
+
+        Message {
+            id,
+            from: bare_address(&from).to_string(),
+            subject: self.headers.get_decoded("Subject").unwrap_or_default(),
+            timestamp,
+            attachments,
+            body: html_body.clone().unwrap_or_else(|| text_body.clone()),
+            text_body,
+            html_body,
+        }
+    }
+}
+
+/// `Name <user@example.com>` to `user@example.com`
+pub(crate) fn bare_address(address: &str) -> &str {
+    match (address.rfind('<'), address.rfind('>')) {
+        (Some(start), Some(end)) if start < end => address[start + 1..end].trim(),
+        _ => address.trim(),
+    }
+}
+
+/// splits a header like `Content-Type` into its lowercase value and its parameters
+fn parse_content_type(value: &str) -> (String, Vec<(String, String)>) {
+    let mut pieces = split_params(value).into_iter();
+    let kind = pieces.next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    let params = pieces
+        .filter_map(|piece| {
+            let (name, value) = piece.split_once('=')?;
+            let name = name.trim().to_ascii_lowercase();
+            let value = unquote(value.trim());
+
+            // RFC 2231 `name*=charset'lang'percent%20encoded`
+            match name.strip_suffix('*') {
+                Some(name) => Some((name.to_string(), decode_extended(&value))),
+                None => Some((name, value)),
+            }
+        })
+        .collect();
+
+    (kind, params)
+}
+
+/// splits on `;` outside of quoted strings
+fn split_params(value: &str) -> Vec<String> {
+    let mut pieces = vec![String::new()];
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in value.chars() {
+        let piece = pieces.last_mut().expect("there's always a piece");
+
+        match c {
+            _ if escaped => {
+                piece.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => {
+                piece.push(c);
+                escaped = true;
+            }
+            '"' => {
+                piece.push(c);
+                quoted = !quoted;
+            }
+            ';' if !quoted => pieces.push(String::new()),
+            _ => piece.push(c),
+        }
+    }
+
+    pieces
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+fn decode_extended(value: &str) -> String {
+    let mut pieces = value.splitn(3, '\'');
+
+    let (charset, encoded) = match (pieces.next(), pieces.next(), pieces.next()) {
+        (Some(charset), Some(_lang), Some(encoded)) => (charset, encoded),
+        _ => ("utf-8", value),
+    };
+
+    decode_charset(&percent_decode(encoded), charset)
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        match (bytes[idx], bytes.get(idx + 1..idx + 3).and_then(hex_byte)) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+fn hex_byte(hex: &[u8]) -> Option<u8> {
+    std::str::from_utf8(hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+}
+
+fn decode_charset(bytes: &[u8], charset: &str) -> String {
+    let encoding = encoding_rs::Encoding::for_label(charset.trim().as_bytes()).unwrap_or(encoding_rs::UTF_8);
+    encoding.decode(bytes).0.into_owned()
+}
+
+/// splits the body of a multipart part on its boundary, dropping the preamble and epilogue
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    let mut parts = Vec::new();
+    let mut current: Option<usize> = None;
+    let mut line_start = 0;
+
+    while line_start < body.len() {
+        let line_end = body[line_start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(body.len(), |idx| line_start + idx + 1);
+        let line = trim_line_end(&body[line_start..line_end]);
+
+        if let Some(closing) = delimiter_line(line, delimiter) {
+            if let Some(start) = current.take() {
+                parts.push(trim_line_end(&body[start..line_start]));
+            }
+
+            if closing {
+                break;
+            }
+
+            current = Some(line_end);
+        }
+
+        line_start = line_end;
+    }
+
+    // a missing closing delimiter still ends the last part
+    if let Some(start) = current {
+        parts.push(&body[start.min(body.len())..]);
+    }
+
+    parts
+}
+
+/// whether a line is the delimiter exactly, `Some(true)` if it's the closing one ending in `--`
+///
+/// only trailing whitespace may follow, so `--abcdef` isn't a delimiter of the boundary `abc`
+fn delimiter_line(line: &[u8], delimiter: &[u8]) -> Option<bool> {
+    let rest = line.strip_prefix(delimiter)?;
+    let (closing, rest) = match rest.strip_prefix(b"--") {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+
+    rest.iter().all(|&b| b == b' ' || b == b'\t').then_some(closing)
+}
+
+fn trim_line_end(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn decode_transfer(body: &[u8], encoding: &str) -> Vec<u8> {
+    match encoding.trim().to_ascii_lowercase().as_str() {
+        "base64" => {
+            let compact: Vec<u8> = body.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+            LENIENT_BASE64.decode(compact).unwrap_or_else(|_| body.to_vec())
+        }
+        "quoted-printable" => decode_quoted_printable(body, false),
+        _ => body.to_vec(),
+    }
+}
+
+/// decodes quoted-printable, `underscores` turns `_` into spaces like in encoded words
+fn decode_quoted_printable(body: &[u8], underscores: bool) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(body.len());
+    let mut idx = 0;
+
+    while idx < body.len() {
+        match body[idx] {
+            b'=' => {
+                let rest = &body[idx + 1..];
+
+                if let Some(byte) = rest.get(..2).and_then(hex_byte) {
+                    decoded.push(byte);
+                    idx += 3;
+                } else if rest.starts_with(b"\r\n") {
+                    idx += 3;
+                } else if rest.starts_with(b"\n") {
+                    idx += 2;
+                } else {
+                    decoded.push(b'=');
+                    idx += 1;
+                }
+            }
+            b'_' if underscores => {
+                decoded.push(b' ');
+                idx += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+/// decodes the RFC 2047 encoded words (`=?charset?B?...?=`) of a header value
+pub fn decode_words(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut after_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let Some(word) = parse_word(&rest[start..]) else {
+            decoded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+
+        // whitespace between two encoded words isn't part of the text
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+
+        decoded.push_str(&word.text);
+        rest = &rest[start + word.len..];
+        after_word = true;
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+struct EncodedWord {
+    text: String,
+    len: usize,
+}
+
+fn parse_word(word: &str) -> Option<EncodedWord> {
+    let inner = word.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let text = &inner[..end];
+
+    if text.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let bytes = match encoding {
+        "B" | "b" => LENIENT_BASE64.decode(text).ok()?,
+        "Q" | "q" => decode_quoted_printable(text.as_bytes(), true),
+        _ => return None,
+    };
+
+    // `=?` charset `?` encoding `?` text `?=`
+    let len = charset.len() + encoding.len() + text.len() + 6;
+
+    // the charset may carry a language, like `utf-8*en`
+    let charset = charset.split('*').next().unwrap_or(charset);
+
+    Some(EncodedWord { text: decode_charset(&bytes, charset), len })
+}
+
+/// finds where the header block ends and the body starts
+pub(crate) fn split_header(raw: &[u8]) -> (usize, usize) {
+    // a part without any headers starts with the blank line
+    if raw.starts_with(b"\r\n") {
+        return (0, 2);
+    } else if raw.starts_with(b"\n") {
+        return (0, 1);
+    }
+
+    let crlf = find(raw, b"\r\n\r\n").map(|idx| (idx, idx + 4));
+    let lf = find(raw, b"\n\n").map(|idx| (idx, idx + 2));
+
+    match (crlf, lf) {
+        (Some(crlf), Some(lf)) => crlf.min(lf),
+        (Some(split), None) | (None, Some(split)) => split,
+        (None, None) => (raw.len(), raw.len()),
+    }
+}
+
+pub(crate) fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MimePart, MAX_DEPTH};
+
+    #[test]
+    fn parses_nested_multiparts() {
+        let raw = b"Content-Type: multipart/mixed; boundary=\"outer\"\r\n\r\n\
+            preamble\r\n\
+            --outer\r\n\
+            Content-Type: multipart/alternative; boundary=inner\r\n\r\n\
+            --inner\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            plain\r\n\
+            --inner\r\n\
+            Content-Type: text/html\r\n\r\n\
+            <p>html</p>\r\n\
+            --inner--\r\n\
+            --outer\r\n\
+            Content-Type: application/pdf\r\n\
+            Content-Transfer-Encoding: base64\r\n\r\n\
+            JVBERg==\r\n\
+            --outer-- \r\n\
+            epilogue\r\n";
+        let root = MimePart::parse(raw);
+
+        assert_eq!(root.parts.len(), 2);
+        assert_eq!(root.parts[0].parts.len(), 2);
+        assert_eq!(root.body_text("plain").as_deref(), Some("plain"));
+        assert_eq!(root.body_text("html").as_deref(), Some("<p>html</p>"));
+        assert_eq!(root.attachments().len(), 1);
+        assert_eq!(root.attachments()[0].body, b"%PDF");
+    }
+
+    #[test]
+    fn only_splits_on_the_exact_delimiter() {
+        let raw = b"Content-Type: multipart/mixed; boundary=abc\n\n\
+            --abc\n\n\
+            first\n\
+            --abcdef\n\
+            still first\n\
+            --abc\t\n\n\
+            second\n\
+            --abc--\n";
+        let root = MimePart::parse(raw);
+
+        let bodies: Vec<&[u8]> = root.parts.iter().map(|part| part.body.as_slice()).collect();
+        assert_eq!(bodies, [&b"first\n--abcdef\nstill first"[..], b"second"]);
+    }
+
+    #[test]
+    fn ends_the_last_part_without_a_closing_delimiter() {
+        let raw = b"Content-Type: multipart/mixed; boundary=b\n\n--b\n\none\n--b\n\ntwo\n";
+        let root = MimePart::parse(raw);
+
+        let bodies: Vec<&[u8]> = root.parts.iter().map(|part| part.body.as_slice()).collect();
+        assert_eq!(bodies, [&b"one"[..], b"two\n"]);
+    }
+
+    #[test]
+    fn decodes_quoted_printable_soft_line_breaks() {
+        let raw = b"Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+            a long line that was wr=\r\napped, caf=C3=A9 =3D 1=\nEUR";
+        assert_eq!(MimePart::parse(raw).text().as_deref(), Some("a long line that was wrapped, caf\u{e9} = 1EUR"));
+    }
+
+    #[test]
+    fn decodes_filenames() {
+        let encoded_word = b"Content-Type: application/pdf; name=\"=?utf-8?B?cmFwcG9ydCDDqXTDqS5wZGY=?=\"\n\nx";
+        assert_eq!(MimePart::parse(encoded_word).filename().as_deref(), Some("rapport \u{e9}t\u{e9}.pdf"));
+
+        let q_word = b"Content-Disposition: attachment; filename=\"=?iso-8859-1?Q?caf=E9_menu.txt?=\"\n\nx";
+        assert_eq!(MimePart::parse(q_word).filename().as_deref(), Some("caf\u{e9} menu.txt"));
+
+        let extended = b"Content-Type: text/plain; name=ignored.txt\n\
+            Content-Disposition: attachment; filename*=UTF-8''na%C3%AFve%20notes.txt\n\nx";
+        let part = MimePart::parse(extended);
+        assert_eq!(part.filename().as_deref(), Some("na\u{ef}ve notes.txt"));
+        assert!(part.is_attachment());
+    }
+
+    #[test]
+    fn stops_splitting_past_the_depth_limit() {
+        let depth = 20_000;
+        let mut raw = Vec::new();
+
+        for level in 0..depth {
+            raw.extend(format!("Content-Type: multipart/mixed; boundary=b{}\n\n--b{}\n", level, level).bytes());
+        }
+        raw.extend(b"\nleaf\n");
+        for level in (0..depth).rev() {
+            raw.extend(format!("--b{}--\n", level).bytes());
+        }
+
+        let root = MimePart::parse(&raw);
+
+        let mut part = &root;
+        let mut levels = 0;
+        while let Some(child) = part.parts.first() {
+            part = child;
+            levels += 1;
+        }
+
+        assert_eq!(levels, MAX_DEPTH);
+        assert!(part.is_multipart());
+        assert!(part.body.starts_with(format!("--b{}\n", MAX_DEPTH).as_bytes()));
+        assert_eq!(root.attachments().len(), 0);
+    }
+}
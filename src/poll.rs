@@ -0,0 +1,185 @@
+use futures::Stream;
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+
+use crate::{Message, RawMessage, Tempmail, TempmailError, TempmailResult};
+
+/// the poll interval never grows past this multiple of the requested one
+const MAX_BACKOFF_FACTOR: u32 = 4;
+
+/// how often [`Tempmail::subscribe`] checks the inbox
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Subscription {
+    inbox: Tempmail,
+    interval: Duration,
+    seen: HashSet<usize>,
+    pending: VecDeque<RawMessage>,
+    polled: bool,
+}
+
+impl Tempmail {
+    /// polls the inbox until a message matching `predicate` shows up and returns it fully read
+    ///
+    /// messages already in the inbox count too. every message is only checked against the
+    /// predicate once, and the time between polls grows from `poll_interval` up to
+    /// 4x `poll_interval` while nothing new arrives. a poll failing with a
+    /// [transient](TempmailError::is_transient) error counts as a poll that found nothing,
+    /// other errors end the wait. fails with [`TempmailError::Timeout`] once `timeout` has passed
+    pub async fn wait_for_message<P>(&self, predicate: P, timeout: Duration, poll_interval: Duration) -> TempmailResult<Message>
+    where
+        P: FnMut(&RawMessage) -> bool,
+    {
+        tokio::time::timeout(timeout, self.poll_for_message(predicate, poll_interval))
+            .await
+            .map_err(|_| TempmailError::Timeout(timeout))?
+    }
+
+    /// returns a stream yielding every message that lands in the inbox, checking it every
+    /// [`DEFAULT_POLL_INTERVAL`]
+    ///
+    /// see [`Tempmail::subscribe_with_interval`]
+    pub fn subscribe(&self) -> impl Stream<Item = TempmailResult<Message>> {
+        self.subscribe_with_interval(DEFAULT_POLL_INTERVAL)
+    }
+
+    /// returns a stream yielding every message that lands in the inbox, checking it every `interval`
+    ///
+    /// messages already in the inbox are yielded first, after that only new ones, oldest first.
+    /// a failed poll or read is yielded as an error and polling carries on, a message that
+    /// couldn't be read being read again after the next poll. nothing is polled while the
+    /// stream isn't being awaited, so dropping it stops polling
+    pub fn subscribe_with_interval(&self, interval: Duration) -> impl Stream<Item = TempmailResult<Message>> {
+        let subscription = Subscription {
+            inbox: self.clone(),
+            interval,
+            seen: HashSet::new(),
+            pending: VecDeque::new(),
+            polled: false,
+        };
+
+        futures::stream::unfold(subscription, |mut sub| async move {
+            loop {
+                if let Some(raw_msg) = sub.pending.pop_front() {
+                    let msg = sub.inbox.read_raw_messsage(&raw_msg).await;
+
+                    // forget a message that couldn't be read, so the next poll picks it up again
+                    if msg.is_err() {
+                        sub.seen.remove(&raw_msg.id);
+                    }
+
+                    return Some((msg, sub));
+                }
+
+                if sub.polled {
+                    tokio::time::sleep(sub.interval).await;
+                }
+                sub.polled = true;
+
+                match sub.inbox.get_raw_messages().await {
+                    Ok(mut raw_msgs) => {
+                        raw_msgs.retain(|raw_msg| sub.seen.insert(raw_msg.id));
+                        raw_msgs.sort_by_key(|raw_msg| (raw_msg.timestamp, raw_msg.id));
+                        sub.pending.extend(raw_msgs);
+                    }
+                    Err(err) => return Some((Err(err), sub)),
+                }
+            }
+        })
+    }
+
+    async fn poll_for_message<P>(&self, mut predicate: P, poll_interval: Duration) -> TempmailResult<Message>
+    where
+        P: FnMut(&RawMessage) -> bool,
+    {
+        let mut seen = HashSet::new();
+        let mut delay = poll_interval;
+
+        loop {
+            let mut found_new = false;
+
+            let raw_msgs = match self.get_raw_messages().await {
+                Err(err) if err.is_transient() => Vec::new(),
+                raw_msgs => raw_msgs?,
+            };
+
+            for raw_msg in raw_msgs {
+                if !seen.insert(raw_msg.id) {
+                    continue;
+                }
+
+                found_new = true;
+
+                if !predicate(&raw_msg) {
+                    continue;
+                }
+
+                match self.read_raw_messsage(&raw_msg).await {
+                    // the message matched, it's read again after the next poll
+                    Err(err) if err.is_transient() => {
+                        seen.remove(&raw_msg.id);
+                        break;
+                    }
+                    res => return res,
+                }
+            }
+
+            if found_new {
+                delay = poll_interval;
+            }
+
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(poll_interval * MAX_BACKOFF_FACTOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use reqwest::StatusCode;
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{provider::MemoryProvider, testing::MockMessage, Domain, TempmailError};
+
+    fn bad_gateway() -> TempmailError {
+        TempmailError::Status { status: StatusCode::BAD_GATEWAY, body: String::new(), retry_after: None }
+    }
+
+    #[tokio::test]
+    async fn waiting_survives_transient_errors() {
+        let provider = Arc::new(MemoryProvider::new());
+        let inbox = provider.inbox("bob", Domain::SecMailCom);
+        provider.mailbox().deliver("bob@1secmail.com", MockMessage::new("alice@example.com", "code"));
+
+        provider.fail_next(bad_gateway());
+        provider.fail_next(TempmailError::RateLimited { retry_after: None });
+        provider.fail_next(bad_gateway());
+
+        let msg = inbox.wait_for_message(|_| true, Duration::from_secs(2), Duration::from_millis(10)).await;
+        assert_eq!(msg.unwrap().subject, "code");
+
+        provider.fail_next(TempmailError::InvalidInput("bad username".to_string()));
+        let res = inbox.wait_for_message(|_| true, Duration::from_secs(2), Duration::from_millis(10)).await;
+        assert!(matches!(res, Err(TempmailError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn subscription_rereads_messages_that_failed() {
+        let provider = Arc::new(MemoryProvider::new());
+        let inbox = provider.inbox("bob", Domain::SecMailCom);
+        provider.mailbox().deliver("bob@1secmail.com", MockMessage::new("alice@example.com", "first"));
+        provider.mailbox().deliver("bob@1secmail.com", MockMessage::new("alice@example.com", "second"));
+
+        let mut stream = Box::pin(inbox.subscribe_with_interval(Duration::from_millis(10)));
+        assert_eq!(stream.next().await.unwrap().unwrap().subject, "first");
+
+        provider.fail_next(bad_gateway());
+        assert_eq!(stream.next().await.unwrap().unwrap_err().status(), Some(StatusCode::BAD_GATEWAY));
+
+        let msg = tokio::time::timeout(Duration::from_secs(2), stream.next()).await.unwrap();
+        assert_eq!(msg.unwrap().unwrap().subject, "second");
+    }
+}
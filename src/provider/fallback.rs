@@ -0,0 +1,307 @@
+use async_trait::async_trait;
+use tokio::io::AsyncWrite;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use super::{address, OneSecMail};
+use crate::{Domain, MailProvider, Message, RawMessage, RetryPolicy, TempmailClient, TempmailError, TempmailResult};
+
+/// how long a health check result is trusted by default
+pub const DEFAULT_HEALTH_TTL: Duration = Duration::from_secs(60);
+
+/// how long a health check waits for an answer by default
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A provider that spreads inboxes over an ordered list of other providers
+///
+/// new inboxes are created on the first provider that's healthy, meaning its domain list
+/// could be fetched within [`DEFAULT_PROBE_TIMEOUT`], and every inbox keeps talking to the
+/// provider it was created on. health checks are cached for [`DEFAULT_HEALTH_TTL`] unless
+/// configured otherwise, and a provider failing at the transport level is marked as down
+/// until its next check
+///
+/// the 1secmail mirrors of [`FallbackProvider::onesecmail_mirrors`] are checked without
+/// retrying, while other providers are checked with their own retry policy
+pub struct FallbackProvider {
+    providers: Vec<Arc<dyn MailProvider>>,
+    /// what health checks go through, the providers themselves unless there's a cheaper way
+    probes: Vec<Arc<dyn MailProvider>>,
+    ttl: Duration,
+    probe_timeout: Duration,
+    health: Mutex<Vec<Option<(Instant, bool)>>>,
+    owners: Mutex<HashMap<String, usize>>,
+}
+
+impl FallbackProvider {
+    pub fn new(providers: Vec<Arc<dyn MailProvider>>) -> Self {
+        let health = Mutex::new(vec![None; providers.len()]);
+
+        Self {
+            probes: providers.clone(),
+            providers,
+            ttl: DEFAULT_HEALTH_TTL,
+            probe_timeout: DEFAULT_PROBE_TIMEOUT,
+            health,
+            owners: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// falls back over 1secmail mirrors, all sharing the connection pool of `client`
+    pub fn onesecmail_mirrors<I, U>(client: &TempmailClient, base_urls: I) -> Self
+    where
+        I: IntoIterator<Item = U>,
+        U: Into<String>,
+    {
+        let clients: Vec<TempmailClient> = base_urls.into_iter().map(|base_url| client.with_base_url(base_url)).collect();
+        let mirror = |client: TempmailClient| Arc::new(OneSecMail::new(client)) as Arc<dyn MailProvider>;
+
+        let mut fallback = Self::new(clients.iter().cloned().map(mirror).collect());
+        fallback.probes = clients
+            .iter()
+            .map(|client| mirror(client.with_retry_policy(RetryPolicy::none())))
+            .collect();
+
+        fallback
+    }
+
+    /// sets how long a health check result is trusted
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// sets how long a health check waits for an answer before deeming the provider down
+    pub fn with_probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    pub fn providers(&self) -> &[Arc<dyn MailProvider>] {
+        &self.providers
+    }
+
+    /// index of the provider an inbox lives on, if it's been used through this fallback yet
+    pub fn owner(&self, username: &str, domain: &Domain) -> Option<usize> {
+        self.owners().get(&address(username, domain)).copied()
+    }
+
+    /// whether a provider is up, probing it if the last check is older than the ttl
+    pub async fn is_healthy(&self, idx: usize) -> bool {
+        let cached = self.health().get(idx).copied().flatten();
+
+        if let Some((checked_at, healthy)) = cached {
+            if checked_at.elapsed() < self.ttl {
+                return healthy;
+            }
+        }
+
+        let healthy = match self.probes.get(idx) {
+            Some(probe) => matches!(tokio::time::timeout(self.probe_timeout, probe.list_domains()).await, Ok(Ok(_))),
+            None => false,
+        };
+
+        self.set_health(idx, healthy);
+        healthy
+    }
+
+    fn health(&self) -> MutexGuard<'_, Vec<Option<(Instant, bool)>>> {
+        self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn owners(&self) -> MutexGuard<'_, HashMap<String, usize>> {
+        self.owners.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn set_health(&self, idx: usize, healthy: bool) {
+        if let Some(entry) = self.health().get_mut(idx) {
+            *entry = Some((Instant::now(), healthy));
+        }
+    }
+
+    async fn first_healthy(&self, skip: usize) -> TempmailResult<usize> {
+        for idx in skip..self.providers.len() {
+            if self.is_healthy(idx).await {
+                return Ok(idx);
+            }
+        }
+
+        Err(TempmailError::Unavailable)
+    }
+
+    /// the provider owning an inbox, handing unknown inboxes to the first healthy one
+    async fn owner_or_healthy(&self, username: &str, domain: &Domain) -> TempmailResult<usize> {
+        if let Some(idx) = self.owner(username, domain) {
+            return Ok(idx);
+        }
+
+        let idx = self.first_healthy(0).await?;
+        self.owners().insert(address(username, domain), idx);
+
+        Ok(idx)
+    }
+
+    /// marks a provider as down when it couldn't be reached at all
+    fn track<T>(&self, idx: usize, res: TempmailResult<T>) -> TempmailResult<T> {
+        if let Err(err) = &res {
+            if is_outage(err) {
+                self.set_health(idx, false);
+            }
+        }
+
+        res
+    }
+}
+
+#[async_trait]
+impl MailProvider for FallbackProvider {
+    async fn create_address(&self) -> TempmailResult<(String, Domain)> {
+        let mut idx = self.first_healthy(0).await?;
+
+        loop {
+            match self.track(idx, self.providers[idx].create_address().await) {
+                Ok((username, domain)) => {
+                    self.owners().insert(address(&username, &domain), idx);
+                    return Ok((username, domain));
+                }
+                Err(err) if is_outage(&err) => idx = self.first_healthy(idx + 1).await?,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn list_domains(&self) -> TempmailResult<Vec<Domain>> {
+        let idx = self.first_healthy(0).await?;
+        self.track(idx, self.providers[idx].list_domains().await)
+    }
+
+    async fn list_messages(&self, username: &str, domain: &Domain) -> TempmailResult<Vec<RawMessage>> {
+        let idx = self.owner_or_healthy(username, domain).await?;
+        self.track(idx, self.providers[idx].list_messages(username, domain).await)
+    }
+
+    async fn read_message(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Message> {
+        let idx = self.owner_or_healthy(username, domain).await?;
+        self.track(idx, self.providers[idx].read_message(username, domain, id).await)
+    }
+
+    async fn download_attachment(&self, username: &str, domain: &Domain, id: usize, filename: &str) -> TempmailResult<Vec<u8>> {
+        let idx = self.owner_or_healthy(username, domain).await?;
+        self.track(idx, self.providers[idx].download_attachment(username, domain, id, filename).await)
+    }
+
+    async fn download_attachment_to(
+        &self,
+        username: &str,
+        domain: &Domain,
+        id: usize,
+        filename: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> TempmailResult<u64> {
+        let idx = self.owner_or_healthy(username, domain).await?;
+        let res = self.providers[idx].download_attachment_to(username, domain, id, filename, writer).await;
+        self.track(idx, res)
+    }
+
+    async fn get_source(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Vec<u8>> {
+        let idx = self.owner_or_healthy(username, domain).await?;
+        self.track(idx, self.providers[idx].get_source(username, domain, id).await)
+    }
+}
+
+fn is_outage(err: &TempmailError) -> bool {
+    match err {
+        TempmailError::Transport(_) => true,
+        TempmailError::Status { status, .. } => status.is_server_error(),
+        TempmailError::Unavailable => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::{is_outage, FallbackProvider};
+    use crate::{
+        testing::{MockMessage, MockServer},
+        MailProvider, RetryPolicy, Tempmail, TempmailClient, TempmailError,
+    };
+
+    /// retries slow enough to notice if the health checks went through them
+    fn client() -> TempmailClient {
+        let retry = RetryPolicy::new().base_delay(Duration::from_secs(2)).jitter(false);
+        TempmailClient::builder().retry_policy(retry).build().unwrap()
+    }
+
+    fn stopped_server() -> String {
+        MockServer::start().unwrap().base_url()
+    }
+
+    #[tokio::test]
+    async fn skips_a_dead_backend() {
+        let live = MockServer::start().unwrap();
+        let fallback = Arc::new(FallbackProvider::onesecmail_mirrors(&client(), [stopped_server(), live.base_url()]));
+
+        let started = Instant::now();
+        let inbox = Tempmail::create(fallback.clone()).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        assert!(!fallback.is_healthy(0).await);
+        assert!(fallback.is_healthy(1).await);
+        assert_eq!(fallback.owner(&inbox.username, &inbox.domain), Some(1));
+
+        live.mailbox().deliver(&inbox.address(), MockMessage::new("alice@example.com", "hi"));
+        assert_eq!(inbox.get_messages().await.unwrap()[0].subject, "hi");
+    }
+
+    #[tokio::test]
+    async fn keeps_inboxes_on_their_backend() {
+        let first = MockServer::start().unwrap();
+        let second = MockServer::start().unwrap();
+        let client = client().with_retry_policy(RetryPolicy::none());
+        let fallback = Arc::new(FallbackProvider::onesecmail_mirrors(&client, [first.base_url(), second.base_url()]));
+
+        let inbox = Tempmail::create(fallback.clone()).await.unwrap();
+        assert_eq!(fallback.owner(&inbox.username, &inbox.domain), Some(0));
+
+        // the message only exists on the other backend, which the inbox never asks
+        second.mailbox().deliver(&inbox.address(), MockMessage::new("alice@example.com", "elsewhere"));
+        assert!(inbox.get_raw_messages().await.unwrap().is_empty());
+
+        // once its backend is gone, the inbox fails instead of moving, and the backend is marked down
+        drop(first);
+        let err = inbox.get_raw_messages().await.unwrap_err();
+        assert!(matches!(err, TempmailError::Transport(_)));
+        assert_eq!(fallback.owner(&inbox.username, &inbox.domain), Some(0));
+        assert!(!fallback.is_healthy(0).await);
+
+        let moved = Tempmail::create(fallback.clone()).await.unwrap();
+        assert_eq!(fallback.owner(&moved.username, &moved.domain), Some(1));
+    }
+
+    #[test]
+    fn outages_are_transport_errors_5xx_and_unavailable() {
+        let status = |status: StatusCode| TempmailError::Status { status, body: String::new(), retry_after: None };
+
+        assert!(is_outage(&status(StatusCode::BAD_GATEWAY)));
+        assert!(is_outage(&TempmailError::Unavailable));
+        assert!(!is_outage(&status(StatusCode::NOT_FOUND)));
+        assert!(!is_outage(&TempmailError::NotFound));
+        assert!(!is_outage(&TempmailError::RateLimited { retry_after: None }));
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_every_backend_is_down() {
+        let fallback = FallbackProvider::onesecmail_mirrors(&client(), [stopped_server(), stopped_server()]);
+
+        assert!(matches!(fallback.create_address().await, Err(TempmailError::Unavailable)));
+        assert!(matches!(fallback.list_domains().await, Err(TempmailError::Unavailable)));
+    }
+}
@@ -0,0 +1,444 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{
+    html, random_username, Domain, MailProvider, Message, RawMessage, TempmailClient, TempmailError, TempmailResult,
+};
+
+const API_URL: &str = "https://api.guerrillamail.com/ajax.php";
+
+/// every one of these delivers into the same inbox, only the username matters
+const DOMAINS: [&str; 9] = [
+    "guerrillamailblock.com",
+    "guerrillamail.com",
+    "guerrillamail.net",
+    "guerrillamail.org",
+    "guerrillamail.biz",
+    "sharklasers.com",
+    "grr.la",
+    "spam4.me",
+    "pokemail.net",
+];
+
+/// The [Guerrilla Mail](https://www.guerrillamail.com) api
+///
+/// inboxes live in server side sessions, identified by a `sid_token` that's sent both as a
+/// param and as the session cookie. a session is started the first time an inbox is used
+/// and new mail is fetched incrementally, by sequence number, on every poll. when a session
+/// expires, the api may move it to a new random address, so a poll that lands on another
+/// address claims the inbox's username again in a new session
+///
+/// the api only serves messages as html: the text body is rendered from it, attachments
+/// aren't listed and downloading them fails with [`TempmailError::Unsupported`]
+pub struct GuerrillaMail {
+    client: TempmailClient,
+    base_url: String,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+#[derive(Clone)]
+struct Session {
+    sid_token: String,
+    /// id of the newest message seen so far
+    seq: usize,
+    messages: Vec<RawMessage>,
+}
+
+#[derive(Deserialize)]
+struct AddressResponse {
+    email_addr: String,
+    sid_token: String,
+}
+
+#[derive(Deserialize)]
+struct CheckResponse {
+    #[serde(default)]
+    list: Vec<MailEntry>,
+    sid_token: Option<String>,
+    /// the address the session is on
+    email: Option<String>,
+    auth: Option<Auth>,
+}
+
+#[derive(Deserialize)]
+struct Auth {
+    success: bool,
+}
+
+#[derive(Deserialize)]
+struct MailEntry {
+    #[serde(deserialize_with = "number")]
+    mail_id: usize,
+    mail_from: String,
+    #[serde(default)]
+    mail_subject: String,
+    #[serde(deserialize_with = "number")]
+    mail_timestamp: usize,
+}
+
+#[derive(Deserialize)]
+struct FetchResponse {
+    mail_from: String,
+    #[serde(default)]
+    mail_subject: String,
+    #[serde(default)]
+    mail_body: String,
+    #[serde(deserialize_with = "number")]
+    mail_timestamp: usize,
+}
+
+/// the api sends numbers as strings most of the time, but not always
+fn number<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Number {
+        Int(usize),
+        Str(String),
+    }
+
+    match Number::deserialize(deserializer)? {
+        Number::Int(n) => Ok(n),
+        Number::Str(s) => s.trim().parse().map_err(serde::de::Error::custom),
+    }
+}
+
+fn timestamp(secs: usize) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs as i64, 0).unwrap_or_default()
+}
+
+impl GuerrillaMail {
+    pub fn new(client: TempmailClient) -> Self {
+        Self::with_base_url(client, API_URL)
+    }
+
+    /// talks to any server speaking the guerrilla mail api, like a mock one
+    pub fn with_base_url<U>(client: TempmailClient, base_url: U) -> Self
+    where
+        U: Into<String>,
+    {
+        Self { client, base_url: base_url.into(), sessions: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// calls an api function, within a session if there's a `sid_token`
+    async fn call<R>(&self, function: &str, params: &[(&str, &str)], sid_token: Option<&str>) -> TempmailResult<R>
+    where
+        R: for<'de> Deserialize<'de>,
+    {
+        let mut req = self
+            .client
+            .http()
+            .get(&self.base_url)
+            .query(&[("f", function), ("ip", "127.0.0.1"), ("agent", "tempmail")])
+            .query(params);
+
+        if let Some(sid_token) = sid_token {
+            req = req
+                .query(&[("sid_token", sid_token)])
+                .header(reqwest::header::COOKIE, format!("PHPSESSID={}", sid_token));
+        }
+
+        self.client.sendjson(req).await
+    }
+
+    /// returns the session of an inbox, starting one if there's none yet
+    async fn session(&self, username: &str) -> TempmailResult<Session> {
+        let key = username.to_ascii_lowercase();
+
+        if let Some(session) = self.sessions().get(&key) {
+            return Ok(session.clone());
+        }
+
+        let session = Session { sid_token: self.claim(username).await?, seq: 0, messages: Vec::new() };
+        self.sessions().insert(key, session.clone());
+
+        Ok(session)
+    }
+
+    /// starts a session on the address of `username`, returning its `sid_token`
+    async fn claim(&self, username: &str) -> TempmailResult<String> {
+        let started: AddressResponse = self.call("get_email_address", &[("lang", "en")], None).await?;
+        let claimed: AddressResponse = self
+            .call("set_email_user", &[("email_user", username), ("lang", "en")], Some(&started.sid_token))
+            .await?;
+
+        let claimed_user = claimed.email_addr.split('@').next().unwrap_or_default();
+        if !claimed_user.eq_ignore_ascii_case(username) {
+            return Err(TempmailError::InvalidAddress(format!("guerrilla mail gave out {} instead of {}", claimed.email_addr, username)));
+        }
+
+        Ok(claimed.sid_token)
+    }
+
+    /// checks for new mail, `None` when the session expired or isn't on the inbox's address anymore
+    async fn check(&self, username: &str, session: &Session) -> TempmailResult<Option<CheckResponse>> {
+        let seq = session.seq.to_string();
+
+        let res: CheckResponse = match self.call("check_email", &[("seq", &seq)], Some(&session.sid_token)).await {
+            Err(err) if matches!(err.status(), Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)) => return Ok(None),
+            res => res?,
+        };
+
+        let expired = res.auth.as_ref().is_some_and(|auth| !auth.success);
+        let moved = res
+            .email
+            .as_deref()
+            .is_some_and(|email| !email.split('@').next().unwrap_or_default().eq_ignore_ascii_case(username));
+
+        Ok((!expired && !moved).then_some(res))
+    }
+
+    fn save_session(&self, username: &str, session: Session) {
+        self.sessions().insert(username.to_ascii_lowercase(), session);
+    }
+
+    /// checks the inbox for new mail and returns its session, claiming the username again in a
+    /// new session if the current one expired or moved to another address
+    async fn refresh(&self, username: &str) -> TempmailResult<Session> {
+        let mut session = self.session(username).await?;
+
+        let res = match self.check(username, &session).await? {
+            Some(res) => res,
+            None => {
+                session.sid_token = self.claim(username).await?;
+                self.check(username, &session).await?.ok_or_else(|| {
+                    TempmailError::InvalidAddress(format!("guerrilla mail won't keep a session on {}", username))
+                })?
+            }
+        };
+
+        if let Some(sid_token) = res.sid_token {
+            session.sid_token = sid_token;
+        }
+
+        for entry in res.list {
+            if session.messages.iter().any(|msg| msg.id == entry.mail_id) {
+                continue;
+            }
+
+            session.messages.push(RawMessage {
+                id: entry.mail_id,
+                from: entry.mail_from,
+                subject: entry.mail_subject,
+                timestamp: timestamp(entry.mail_timestamp),
+            });
+        }
+
+        session.seq = session.messages.iter().map(|msg| msg.id).max().unwrap_or(session.seq);
+        session.messages.sort_by_key(|msg| std::cmp::Reverse(msg.id));
+
+        self.save_session(username, session.clone());
+        Ok(session)
+    }
+}
+
+#[async_trait]
+impl MailProvider for GuerrillaMail {
+    async fn create_address(&self) -> TempmailResult<(String, Domain)> {
+        let username = random_username().to_ascii_lowercase();
+        self.session(&username).await?;
+
+        Ok((username, Domain::from_name(DOMAINS[0])))
+    }
+
+    async fn list_domains(&self) -> TempmailResult<Vec<Domain>> {
+        Ok(DOMAINS.iter().map(Domain::from_name).collect())
+    }
+
+    async fn list_messages(&self, username: &str, _domain: &Domain) -> TempmailResult<Vec<RawMessage>> {
+        Ok(self.refresh(username).await?.messages)
+    }
+
+    async fn read_message(&self, username: &str, _domain: &Domain, id: usize) -> TempmailResult<Message> {
+        let session = self.refresh(username).await?;
+        let id_param = id.to_string();
+
+        let value: serde_json::Value = self.call("fetch_email", &[("email_id", &id_param)], Some(&session.sid_token)).await?;
+
+        // the api answers with `false` for ids that aren't in the inbox
+        if !value.is_object() {
+            return Err(TempmailError::NotFound);
+        }
+
+        let body = value.to_string();
+        let res: FetchResponse = serde_json::from_value(value).map_err(|err| TempmailError::decode(err, &body))?;
+
+        let msg = Message {
+            id,
+            from: res.mail_from,
+            subject: res.mail_subject,
+            timestamp: timestamp(res.mail_timestamp),
+            attachments: Vec::new(),
+            body: res.mail_body.clone(),
+            text_body: html::to_text(&res.mail_body),
+            html_body: Some(res.mail_body),
+        };
+
+        Ok(msg.normalized())
+    }
+
+    async fn download_attachment(&self, _username: &str, _domain: &Domain, _id: usize, _filename: &str) -> TempmailResult<Vec<u8>> {
+        Err(TempmailError::Unsupported("guerrilla mail attachments"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response,
+    };
+    use serde_json::{json, Value};
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    use super::GuerrillaMail;
+    use crate::{
+        testing::{local_listener, Background},
+        Domain, MailProvider, TempmailClient,
+    };
+
+    /// addresses by `sid_token`, a session moving to `moved@` once it expires
+    type Sessions = Arc<Mutex<HashMap<String, String>>>;
+
+    struct MockGuerrilla {
+        base_url: String,
+        sessions: Sessions,
+        _background: Background,
+    }
+
+    impl MockGuerrilla {
+        fn start() -> Self {
+            let listener = local_listener().unwrap();
+            let base_url = format!("http://{}/ajax.php", listener.local_addr().unwrap());
+            let sessions = Sessions::default();
+            let served = sessions.clone();
+
+            let background = Background::spawn(move |stopped| async move {
+                let make_service = make_service_fn(move |_| {
+                    let sessions = served.clone();
+                    async move { Ok::<_, Infallible>(service_fn(move |req| handle(sessions.clone(), req))) }
+                });
+
+                let server = hyper::Server::from_tcp(listener).unwrap().serve(make_service);
+                let _ = server
+                    .with_graceful_shutdown(async {
+                        let _ = stopped.await;
+                    })
+                    .await;
+            })
+            .unwrap();
+
+            Self { base_url, sessions, _background: background }
+        }
+
+        fn expire_sessions(&self) {
+            for email in self.sessions.lock().unwrap().values_mut() {
+                *email = "moved@guerrillamailblock.com".to_string();
+            }
+        }
+    }
+
+    async fn handle(sessions: Sessions, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let query: HashMap<String, String> = reqwest::Url::parse(&format!("http://mock{}", req.uri()))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        let param = |name: &str| query.get(name).cloned().unwrap_or_default();
+
+        let mut sessions = sessions.lock().unwrap();
+        let body = match param("f").as_str() {
+            "get_email_address" => {
+                let sid_token = format!("sid{}", sessions.len() + 1);
+                sessions.insert(sid_token.clone(), "random@guerrillamailblock.com".to_string());
+                json!({ "email_addr": "random@guerrillamailblock.com", "sid_token": sid_token })
+            }
+            "set_email_user" => {
+                let email = format!("{}@guerrillamailblock.com", param("email_user"));
+                sessions.insert(param("sid_token"), email.clone());
+                json!({ "email_addr": email, "sid_token": param("sid_token") })
+            }
+            "check_email" => {
+                let email = sessions.get(&param("sid_token")).cloned().unwrap_or_default();
+                let list: Vec<Value> = match email.starts_with("bob@") {
+                    true => vec![json!({
+                        "mail_id": "7",
+                        "mail_from": "alice@example.com",
+                        "mail_subject": "Hi",
+                        "mail_timestamp": "1700000000",
+                    })],
+                    false => Vec::new(),
+                };
+                json!({ "list": list, "email": email, "sid_token": param("sid_token") })
+            }
+            "fetch_email" if !sessions.get(&param("sid_token")).is_some_and(|email| email.starts_with("bob@")) => {
+                json!(false)
+            }
+            _ => json!({
+                "mail_id": "7",
+                "mail_from": "alice@example.com",
+                "mail_subject": "Hi",
+                "mail_body": "<p>Hello <b>bob</b></p>",
+                "mail_timestamp": "1700000000",
+            }),
+        };
+
+        Ok(Response::new(Body::from(body.to_string())))
+    }
+
+    #[tokio::test]
+    async fn claims_the_address_again_when_the_session_moves() {
+        let server = MockGuerrilla::start();
+        let guerrilla = GuerrillaMail::with_base_url(TempmailClient::default(), &server.base_url);
+        let domain = Domain::from_name("guerrillamailblock.com");
+
+        assert_eq!(guerrilla.list_messages("bob", &domain).await.unwrap().len(), 1);
+
+        server.expire_sessions();
+        let raw_msgs = guerrilla.list_messages("bob", &domain).await.unwrap();
+        assert_eq!(raw_msgs.len(), 1);
+        assert_eq!(raw_msgs[0].subject, "Hi");
+
+        let sessions = server.sessions.lock().unwrap().clone();
+        assert_eq!(sessions.values().filter(|email| email.starts_with("bob@")).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn reads_through_a_moved_session() {
+        let server = MockGuerrilla::start();
+        let guerrilla = GuerrillaMail::with_base_url(TempmailClient::default(), &server.base_url);
+        let domain = Domain::from_name("guerrillamailblock.com");
+
+        assert_eq!(guerrilla.list_messages("bob", &domain).await.unwrap().len(), 1);
+
+        server.expire_sessions();
+        assert_eq!(guerrilla.read_message("bob", &domain, 7).await.unwrap().subject, "Hi");
+    }
+
+    #[tokio::test]
+    async fn renders_the_text_body() {
+        let server = MockGuerrilla::start();
+        let guerrilla = GuerrillaMail::with_base_url(TempmailClient::default(), &server.base_url);
+
+        let msg = guerrilla.read_message("bob", &Domain::from_name("guerrillamailblock.com"), 7).await.unwrap();
+        assert_eq!(msg.text_body, "Hello bob");
+        assert_eq!(msg.html_body.as_deref(), Some("<p>Hello <b>bob</b></p>"));
+    }
+}
@@ -0,0 +1,468 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::HashMap, sync::Mutex};
+use tokio::io::AsyncWrite;
+
+use super::address;
+use crate::{
+    client::{decode_json, write_body},
+    random_string, random_username, Attachment, Domain, MailProvider, Message, RawMessage,
+    TempmailClient, TempmailError, TempmailResult,
+};
+
+const MAIL_TM_URL: &str = "https://api.mail.tm";
+const MAIL_GW_URL: &str = "https://api.mail.gw";
+
+/// how many messages the api returns per page
+const PAGE_SIZE: usize = 30;
+
+/// The [mail.tm](https://mail.tm) api, also used by its clone [mail.gw](https://mail.gw)
+///
+/// every address is a password protected account. the passwords and the jwt tokens of the
+/// accounts are kept in memory, so an address created through one `MailTm` can only be read
+/// through that same value unless it's registered with [`MailTm::add_account`]
+///
+/// the api identifies messages with strings, they're mapped to numeric ids in the order
+/// they're first listed
+pub struct MailTm {
+    client: TempmailClient,
+    base_url: String,
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+#[derive(Clone)]
+struct Account {
+    password: String,
+    token: Option<String>,
+    /// remote ids, a message's numeric id is its index in here plus one
+    message_ids: Vec<String>,
+}
+
+/// collections come wrapped in `hydra:member` unless the api is asked for plain json
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Collection<T> {
+    Hydra {
+        #[serde(rename = "hydra:member")]
+        member: Vec<T>,
+    },
+    Plain(Vec<T>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DomainEntry {
+    domain: String,
+    is_active: bool,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Sender {
+    address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageEntry {
+    id: String,
+    from: Sender,
+    #[serde(default)]
+    subject: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageDetail {
+    from: Sender,
+    #[serde(default)]
+    subject: String,
+    created_at: DateTime<Utc>,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    html: Vec<String>,
+    #[serde(default)]
+    attachments: Vec<AttachmentEntry>,
+}
+
+#[derive(Deserialize)]
+struct Source {
+    data: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachmentEntry {
+    filename: String,
+    content_type: String,
+    size: usize,
+    download_url: String,
+}
+
+impl<T> Collection<T> {
+    fn into_vec(self) -> Vec<T> {
+        match self {
+            Collection::Hydra { member } => member,
+            Collection::Plain(items) => items,
+        }
+    }
+}
+
+impl MailTm {
+    /// talks to `https://api.mail.tm`
+    pub fn new(client: TempmailClient) -> Self {
+        Self::with_base_url(client, MAIL_TM_URL)
+    }
+
+    /// talks to `https://api.mail.gw`
+    pub fn mail_gw(client: TempmailClient) -> Self {
+        Self::with_base_url(client, MAIL_GW_URL)
+    }
+
+    /// talks to any server speaking the mail.tm api, like a mock one
+    pub fn with_base_url<U>(client: TempmailClient, base_url: U) -> Self
+    where
+        U: Into<String>,
+    {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self { client, base_url, accounts: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// registers an existing account so its inbox can be read
+    pub fn add_account<U, P>(&self, username: U, domain: &Domain, password: P)
+    where
+        U: AsRef<str>,
+        P: Into<String>,
+    {
+        let account = Account { password: password.into(), token: None, message_ids: Vec::new() };
+        self.accounts().insert(address(username.as_ref(), domain), account);
+    }
+
+    /// the password of an account created or registered through this provider
+    pub fn password(&self, username: &str, domain: &Domain) -> Option<String> {
+        self.accounts()
+            .get(&address(username, domain))
+            .map(|account| account.password.clone())
+    }
+
+    fn accounts(&self) -> std::sync::MutexGuard<'_, HashMap<String, Account>> {
+        self.accounts.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn account(&self, address: &str) -> TempmailResult<Account> {
+        self.accounts()
+            .get(address)
+            .cloned()
+            .ok_or_else(|| TempmailError::InvalidInput(format!("no mail.tm account known for {}", address)))
+    }
+
+    /// returns the cached token of an account, logging in again when there's none or `refresh` is set
+    async fn token(&self, address: &str, refresh: bool) -> TempmailResult<String> {
+        let account = self.account(address)?;
+
+        if let (Some(token), false) = (account.token, refresh) {
+            return Ok(token);
+        }
+
+        let req = self
+            .client
+            .http()
+            .post(self.url("/token"))
+            .json(&json!({ "address": address, "password": account.password }));
+        let Token { token } = self.client.sendjson(req).await?;
+
+        if let Some(account) = self.accounts().get_mut(address) {
+            account.token = Some(token.clone());
+        }
+
+        Ok(token)
+    }
+
+    /// does an authenticated get req, logging in again once if the token expired
+    async fn authed_get(&self, address: &str, path: &str) -> TempmailResult<reqwest::Response> {
+        let token = self.token(address, false).await?;
+        let req = self.client.http().get(self.url(path)).bearer_auth(token);
+
+        match self.client.send(req).await {
+            Err(err) if err.status() == Some(StatusCode::UNAUTHORIZED) => {
+                let token = self.token(address, true).await?;
+                self.client.send(self.client.http().get(self.url(path)).bearer_auth(token)).await
+            }
+            res => res,
+        }
+    }
+
+    async fn authed_json<R>(&self, address: &str, path: &str) -> TempmailResult<R>
+    where
+        R: for<'de> Deserialize<'de>,
+    {
+        let body = self.authed_get(address, path).await?.text().await?;
+        decode_json(&body)
+    }
+
+    /// maps remote message ids to numeric ones, handing out new ids oldest first
+    fn numeric_ids(&self, address: &str, remote_ids: &[&str]) -> TempmailResult<Vec<usize>> {
+        let mut accounts = self.accounts();
+        let account = accounts
+            .get_mut(address)
+            .ok_or_else(|| TempmailError::InvalidInput(format!("no mail.tm account known for {}", address)))?;
+
+        for remote_id in remote_ids.iter().rev() {
+            if !account.message_ids.iter().any(|id| id == remote_id) {
+                account.message_ids.push(remote_id.to_string());
+            }
+        }
+
+        Ok(remote_ids
+            .iter()
+            .map(|remote_id| account.message_ids.iter().position(|id| id == remote_id).unwrap_or_default() + 1)
+            .collect())
+    }
+
+    fn remote_id(&self, address: &str, id: usize) -> TempmailResult<String> {
+        self.account(address)?
+            .message_ids
+            .get(id.wrapping_sub(1))
+            .cloned()
+            .ok_or(TempmailError::NotFound)
+    }
+
+    async fn message_detail(&self, address: &str, id: usize) -> TempmailResult<MessageDetail> {
+        let remote_id = self.remote_id(address, id)?;
+        self.authed_json(address, &format!("/messages/{}", remote_id)).await
+    }
+
+    /// the path an attachment of a message is downloaded from
+    async fn attachment_url(&self, address: &str, id: usize, filename: &str) -> TempmailResult<String> {
+        self.message_detail(address, id)
+            .await?
+            .attachments
+            .into_iter()
+            .find(|att| att.filename == filename)
+            .map(|att| att.download_url)
+            .ok_or(TempmailError::NotFound)
+    }
+}
+
+#[async_trait]
+impl MailProvider for MailTm {
+    async fn create_address(&self) -> TempmailResult<(String, Domain)> {
+        let domain = Domain::pick(self.list_domains().await?)?;
+        let username = random_username().to_ascii_lowercase();
+        let password = random_string(24);
+
+        let req = self
+            .client
+            .http()
+            .post(self.url("/accounts"))
+            .json(&json!({ "address": address(&username, &domain), "password": password }));
+        self.client.send(req).await?;
+
+        self.add_account(&username, &domain, password);
+        Ok((username, domain))
+    }
+
+    async fn list_domains(&self) -> TempmailResult<Vec<Domain>> {
+        let req = self.client.http().get(self.url("/domains"));
+        let domains: Collection<DomainEntry> = self.client.sendjson(req).await?;
+
+        Ok(domains
+            .into_vec()
+            .into_iter()
+            .filter(|entry| entry.is_active)
+            .map(|entry| Domain::from_name(entry.domain))
+            .collect())
+    }
+
+    async fn list_messages(&self, username: &str, domain: &Domain) -> TempmailResult<Vec<RawMessage>> {
+        let address = address(username, domain);
+        let mut entries = Vec::new();
+
+        for page in 1.. {
+            let batch: Collection<MessageEntry> = self.authed_json(&address, &format!("/messages?page={}", page)).await?;
+            let batch = batch.into_vec();
+            let done = batch.len() < PAGE_SIZE;
+
+            entries.extend(batch);
+
+            if done {
+                break;
+            }
+        }
+
+        let remote_ids: Vec<&str> = entries.iter().map(|entry| entry.id.as_str()).collect();
+        let ids = self.numeric_ids(&address, &remote_ids)?;
+
+        Ok(entries
+            .into_iter()
+            .zip(ids)
+            .map(|(entry, id)| RawMessage { id, from: entry.from.address, subject: entry.subject, timestamp: entry.created_at })
+            .collect())
+    }
+
+    async fn read_message(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Message> {
+        let detail = self.message_detail(&address(username, domain), id).await?;
+        let html_body = detail.html.concat();
+
+        let msg = Message {
+            id,
+            from: detail.from.address,
+            subject: detail.subject,
+            timestamp: detail.created_at,
+            attachments: detail
+                .attachments
+                .into_iter()
+                .map(|att| Attachment { filename: att.filename, content_type: att.content_type, size: att.size })
+                .collect(),
+            body: if html_body.is_empty() { detail.text.clone() } else { html_body.clone() },
+            text_body: detail.text,
+            html_body: Some(html_body),
+        };
+
+        Ok(msg.normalized())
+    }
+
+    async fn download_attachment(&self, username: &str, domain: &Domain, id: usize, filename: &str) -> TempmailResult<Vec<u8>> {
+        let address = address(username, domain);
+        let url = self.attachment_url(&address, id, filename).await?;
+
+        Ok(self.authed_get(&address, &url).await?.bytes().await?.to_vec())
+    }
+
+    async fn download_attachment_to(
+        &self,
+        username: &str,
+        domain: &Domain,
+        id: usize,
+        filename: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> TempmailResult<u64> {
+        let address = address(username, domain);
+        let url = self.attachment_url(&address, id, filename).await?;
+
+        write_body(self.authed_get(&address, &url).await?, writer).await
+    }
+
+    async fn get_source(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Vec<u8>> {
+        let address = address(username, domain);
+        let remote_id = self.remote_id(&address, id)?;
+
+        let source: Source = self.authed_json(&address, &format!("/sources/{}", remote_id)).await?;
+        Ok(source.data.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use std::{sync::Arc, time::Duration};
+
+    use super::{Collection, MessageEntry};
+    use crate::{
+        testing::{MailTmServer, MAIL_TM_ATTACHMENT},
+        Domain, MailProvider, RetryPolicy, Tempmail, TempmailClient,
+    };
+
+    fn client(retry: RetryPolicy) -> TempmailClient {
+        TempmailClient::builder().retry_policy(retry).build().unwrap()
+    }
+
+    fn count(server: &MailTmServer, request: &str) -> usize {
+        server.requests().iter().filter(|sent| sent.starts_with(request)).count()
+    }
+
+    #[tokio::test]
+    async fn reads_the_mock_inbox() {
+        let server = MailTmServer::start().unwrap();
+        let mail_tm = Arc::new(server.provider(client(RetryPolicy::none())));
+
+        assert_eq!(mail_tm.list_domains().await.unwrap(), [Domain::from_name("punkproof.com")]);
+
+        let inbox = Tempmail::create(mail_tm.clone()).await.unwrap();
+        assert_eq!(inbox.domain, Domain::from_name("punkproof.com"));
+        assert!(mail_tm.password(&inbox.username, &inbox.domain).is_some());
+
+        let raw_msgs = inbox.get_raw_messages().await.unwrap();
+        assert_eq!(raw_msgs.len(), 31);
+        assert_eq!((raw_msgs[0].id, raw_msgs[0].subject.as_str()), (31, "Message 31"));
+        assert_eq!((raw_msgs[30].id, raw_msgs[30].subject.as_str()), (1, "Message 1"));
+        assert_eq!(count(&server, "GET /messages?page=2"), 1);
+        assert_eq!(count(&server, "GET /messages?page=3"), 0);
+
+        let msg = inbox.read_raw_messsage(&raw_msgs[0]).await.unwrap();
+        assert_eq!(msg.from, "news@shop.example");
+        assert_eq!(msg.text_body, "Hi,\n\nyour invoice is attached.\n");
+        assert_eq!(msg.attachments[0].filename, "invoice.txt");
+        assert_eq!(msg.attachments[0].size, MAIL_TM_ATTACHMENT.len());
+
+        assert_eq!(inbox.get_attachment(31, "invoice.txt").await.unwrap(), MAIL_TM_ATTACHMENT);
+        assert_eq!(inbox.get_source(31).await.unwrap().header("subject"), Some("Message 31"));
+    }
+
+    #[tokio::test]
+    async fn logs_in_again_once_the_token_expires() {
+        let server = MailTmServer::start().unwrap();
+        let inbox = Tempmail::create(Arc::new(server.provider(client(RetryPolicy::none())))).await.unwrap();
+
+        inbox.get_raw_messages().await.unwrap();
+        assert_eq!(count(&server, "POST /token"), 1);
+
+        server.expire_tokens();
+        assert_eq!(inbox.get_raw_messages().await.unwrap().len(), 31);
+        assert_eq!(count(&server, "POST /token"), 2);
+
+        let requests = server.requests();
+        let relogin = &requests[requests.len() - 4..requests.len() - 1];
+        assert_eq!(relogin, ["GET /messages?page=1", "POST /token", "GET /messages?page=1"]);
+    }
+
+    #[tokio::test]
+    async fn only_retries_idempotent_requests() {
+        let server = MailTmServer::start().unwrap();
+        let retry = RetryPolicy::new().base_delay(Duration::from_millis(1));
+        let mail_tm = server.provider(client(retry));
+
+        server.fail_next("GET /domains", StatusCode::SERVICE_UNAVAILABLE);
+        server.fail_next("POST /accounts", StatusCode::SERVICE_UNAVAILABLE);
+
+        let err = mail_tm.create_address().await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(count(&server, "GET /domains"), 2);
+        assert_eq!(count(&server, "POST /accounts"), 1);
+    }
+
+    #[test]
+    fn reads_plain_json_collections() {
+        let plain = r#"[{
+            "id": "21f6be6abf0d7c1c1e21862a",
+            "from": { "address": "news@shop.example", "name": "Shop" },
+            "subject": "Message 1",
+            "createdAt": "2024-02-01T10:01:00+00:00"
+        }]"#;
+
+        let entries = serde_json::from_str::<Collection<MessageEntry>>(plain).unwrap().into_vec();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, "21f6be6abf0d7c1c1e21862a");
+        assert_eq!(entries[0].from.address, "news@shop.example");
+
+        let empty = serde_json::from_str::<Collection<MessageEntry>>("[]").unwrap().into_vec();
+        assert!(empty.is_empty());
+    }
+}
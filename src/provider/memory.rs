@@ -0,0 +1,590 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rand::seq::SliceRandom;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use super::address;
+use crate::{
+    mime::MimePart, random_username, Attachment, Domain, MailProvider, Message, RawMessage, Tempmail, TempmailError,
+    TempmailResult,
+};
+
+/// An inbox backend that never leaves the process, for unit tests
+///
+/// it reads a [`MockMailbox`], which can also be served over http by a `MockServer` with the
+/// `testing` feature, while this provider itself needs no http. tests deliver messages with
+/// [`MemoryProvider::deliver`] or into [`MemoryProvider::mailbox`] and read them back through
+/// a regular [`Tempmail`]
+///
+/// to exercise the code reading the inbox, calls can be slowed down with
+/// [`MemoryProvider::set_latency`], made to fail with [`MemoryProvider::fail_next`], and
+/// messages can show up late with [`MockMailbox::deliver_after`] or be listed in random
+/// order with [`MemoryProvider::shuffle_listings`]
+pub struct MemoryProvider {
+    mailbox: MockMailbox,
+    hooks: Mutex<Hooks>,
+}
+
+#[derive(Default)]
+struct Hooks {
+    latency: Duration,
+    failures: VecDeque<TempmailError>,
+    shuffle: bool,
+}
+
+/// A message to deliver into a [`MockMailbox`]
+#[derive(Clone, Debug)]
+pub struct MockMessage {
+    from: String,
+    subject: String,
+    timestamp: Option<DateTime<Utc>>,
+    text_body: String,
+    html_body: Option<String>,
+    attachments: Vec<(Attachment, Vec<u8>)>,
+    source: Option<Vec<u8>>,
+}
+
+/// The shared in-memory store a [`MemoryProvider`] reads, and a `MockServer` serves with the
+/// `testing` feature
+///
+/// cloning is cheap and every clone sees the same messages. ids are handed out globally in
+/// delivery order, starting at 1, like the api does
+#[derive(Clone)]
+pub struct MockMailbox {
+    store: Arc<Mutex<Store>>,
+}
+
+struct Store {
+    domains: Vec<Domain>,
+    next_id: usize,
+    messages: Vec<StoredMessage>,
+}
+
+pub(crate) struct StoredMessage {
+    /// lowercase `username@domain`
+    address: String,
+    pub(crate) message: Message,
+    pub(crate) attachments: HashMap<String, Vec<u8>>,
+    pub(crate) source: Vec<u8>,
+    /// when the message shows up in the inbox
+    visible_at: Instant,
+}
+
+impl Default for MemoryProvider {
+    fn default() -> Self {
+        Self::with_mailbox(MockMailbox::new())
+    }
+}
+
+impl MemoryProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a provider reading the given mailbox, which can be shared with a mock server
+    pub fn with_mailbox(mailbox: MockMailbox) -> Self {
+        Self { mailbox, hooks: Mutex::new(Hooks::default()) }
+    }
+
+    /// the mailbox to deliver messages into
+    pub fn mailbox(&self) -> &MockMailbox {
+        &self.mailbox
+    }
+
+    /// delivers a message with the content of its attachments to an address, returning the id
+    /// it was given instead of its own
+    pub fn deliver(&self, address: &str, message: Message, attachments: Vec<(Attachment, Vec<u8>)>) -> usize {
+        let message = attachments
+            .into_iter()
+            .fold(MockMessage::from(message), |message, (attachment, data)| {
+                message.attachment(attachment.filename, attachment.content_type, data)
+            });
+
+        self.mailbox.deliver(address, message)
+    }
+
+    fn hooks(&self) -> MutexGuard<'_, Hooks> {
+        self.hooks.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// an inbox reading from this provider
+    pub fn inbox<U>(self: &Arc<Self>, username: U, domain: Domain) -> Tempmail
+    where
+        U: Into<String>,
+    {
+        Tempmail::with_provider(self.clone(), username, Some(domain))
+    }
+
+    /// every call waits this long before answering
+    pub fn set_latency(&self, latency: Duration) {
+        self.hooks().latency = latency;
+    }
+
+    /// makes the next call fail with the given error, calling it again queues more failures
+    pub fn fail_next(&self, err: TempmailError) {
+        self.hooks().failures.push_back(err);
+    }
+
+    /// lists messages in random order instead of newest first
+    pub fn shuffle_listings(&self, shuffle: bool) {
+        self.hooks().shuffle = shuffle;
+    }
+
+    /// waits out the latency and pops the next queued failure
+    async fn run_hooks(&self) -> TempmailResult<()> {
+        let latency = self.hooks().latency;
+
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+
+        match self.hooks().failures.pop_front() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn with_message<R, F>(&self, username: &str, domain: &Domain, id: usize, f: F) -> TempmailResult<R>
+    where
+        F: FnOnce(&StoredMessage) -> Option<R>,
+    {
+        let address = address(username, domain).to_ascii_lowercase();
+
+        self.mailbox
+            .with_message(&address, id, f)
+            .flatten()
+            .ok_or(TempmailError::NotFound)
+    }
+}
+
+#[async_trait]
+impl MailProvider for MemoryProvider {
+    async fn create_address(&self) -> TempmailResult<(String, Domain)> {
+        self.run_hooks().await?;
+        Ok((random_username(), Domain::pick(self.mailbox.domains())?))
+    }
+
+    async fn list_domains(&self) -> TempmailResult<Vec<Domain>> {
+        self.run_hooks().await?;
+        Ok(self.mailbox.domains())
+    }
+
+    async fn list_messages(&self, username: &str, domain: &Domain) -> TempmailResult<Vec<RawMessage>> {
+        self.run_hooks().await?;
+
+        let mut messages: Vec<RawMessage> = self
+            .mailbox
+            .messages(&address(username, domain))
+            .into_iter()
+            .rev()
+            .map(|msg| RawMessage { id: msg.id, from: msg.from, subject: msg.subject, timestamp: msg.timestamp })
+            .collect();
+
+        if self.hooks().shuffle {
+            messages.shuffle(&mut rand::thread_rng());
+        }
+
+        Ok(messages)
+    }
+
+    async fn read_message(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Message> {
+        self.run_hooks().await?;
+        self.with_message(username, domain, id, |stored| Some(stored.message.clone()))
+    }
+
+    async fn download_attachment(&self, username: &str, domain: &Domain, id: usize, filename: &str) -> TempmailResult<Vec<u8>> {
+        self.run_hooks().await?;
+        self.with_message(username, domain, id, |stored| stored.attachments.get(filename).cloned())
+    }
+
+    async fn get_source(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Vec<u8>> {
+        self.run_hooks().await?;
+        self.with_message(username, domain, id, |stored| Some(stored.source.clone()))
+    }
+}
+
+impl MockMessage {
+    pub fn new<F, S>(from: F, subject: S) -> Self
+    where
+        F: Into<String>,
+        S: Into<String>,
+    {
+        Self {
+            from: from.into(),
+            subject: subject.into(),
+            timestamp: None,
+            text_body: String::new(),
+            html_body: None,
+            attachments: Vec::new(),
+            source: None,
+        }
+    }
+
+    pub fn text<B>(mut self, body: B) -> Self
+    where
+        B: Into<String>,
+    {
+        self.text_body = body.into();
+        self
+    }
+
+    pub fn html<B>(mut self, body: B) -> Self
+    where
+        B: Into<String>,
+    {
+        self.html_body = Some(body.into());
+        self
+    }
+
+    /// when the message was received, the time of delivery if not set
+    pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn attachment<N, T, D>(mut self, filename: N, content_type: T, data: D) -> Self
+    where
+        N: Into<String>,
+        T: Into<String>,
+        D: Into<Vec<u8>>,
+    {
+        let data = data.into();
+        let attachment = Attachment { filename: filename.into(), content_type: content_type.into(), size: data.len() };
+
+        self.attachments.push((attachment, data));
+        self
+    }
+
+    /// the raw source served for the message, a minimal one is made up from the text body if not set
+    pub fn source<R>(mut self, raw: R) -> Self
+    where
+        R: Into<Vec<u8>>,
+    {
+        self.source = Some(raw.into());
+        self
+    }
+}
+
+impl Default for MockMailbox {
+    fn default() -> Self {
+        let store = Store { domains: Domain::DOMAINS.to_vec(), next_id: 1, messages: Vec::new() };
+        Self { store: Arc::new(Mutex::new(store)) }
+    }
+}
+
+impl MockMailbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// the domains `getDomainList` answers with, the known 1secmail ones by default
+    pub fn set_domains(&self, domains: Vec<Domain>) {
+        self.store().domains = domains;
+    }
+
+    pub fn domains(&self) -> Vec<Domain> {
+        self.store().domains.clone()
+    }
+
+    /// delivers a message to an address and returns its id
+    pub fn deliver(&self, address: &str, message: MockMessage) -> usize {
+        self.deliver_after(address, message, Duration::ZERO)
+    }
+
+    /// like [`MockMailbox::deliver`], the message only showing up in the inbox after `delay`
+    ///
+    /// a message delivered with a longer delay than the next one arrives after it, while
+    /// still having the lower id
+    pub fn deliver_after(&self, address: &str, message: MockMessage, delay: Duration) -> usize {
+        let visible_at = Instant::now() + delay;
+        let mut store = self.store();
+        let id = store.next_id;
+        store.next_id += 1;
+
+        let timestamp = message.timestamp.unwrap_or_else(Utc::now);
+        let source = message
+            .source
+            .unwrap_or_else(|| minimal_source(address, &message.from, &message.subject, timestamp, &message.text_body));
+
+        let (attachments, data): (Vec<Attachment>, Vec<Vec<u8>>) = message.attachments.into_iter().unzip();
+        let data = attachments.iter().map(|att| att.filename.clone()).zip(data).collect();
+
+        let message = Message {
+            id,
+            from: message.from,
+            subject: message.subject,
+            timestamp,
+            attachments,
+            body: message.html_body.clone().unwrap_or_else(|| message.text_body.clone()),
+            text_body: message.text_body,
+            html_body: message.html_body,
+        };
+
+        store.messages.push(StoredMessage {
+            address: address.trim().to_ascii_lowercase(),
+            message,
+            attachments: data,
+            source,
+            visible_at,
+        });
+        id
+    }
+
+    /// parses a raw RFC 822 message and delivers it to an address, returning its id
+    ///
+    /// the bodies and attachments come from its MIME parts, and the raw bytes are served as
+    /// its source
+    pub fn deliver_raw(&self, address: &str, raw: &[u8]) -> usize {
+        let mime = MimePart::parse(raw);
+        let projected = mime.to_message(0);
+
+        let mut message = MockMessage::new(projected.from, projected.subject)
+            .text(projected.text_body)
+            .timestamp(projected.timestamp)
+            .source(raw);
+        message.html_body = projected.html_body;
+
+        for part in mime.attachments() {
+            message = message.attachment(part.attachment_name(), part.content_type.clone(), part.body.clone());
+        }
+
+        self.deliver(address, message)
+    }
+
+    /// every message delivered to an address, oldest first, leaving out the ones delivered
+    /// with a delay that hasn't passed yet
+    pub fn messages(&self, address: &str) -> Vec<Message> {
+        let address = address.trim().to_ascii_lowercase();
+        let now = Instant::now();
+
+        self.store()
+            .messages
+            .iter()
+            .filter(|stored| stored.address == address && stored.visible_at <= now)
+            .map(|stored| stored.message.clone())
+            .collect()
+    }
+
+    /// removes every message
+    pub fn clear(&self) {
+        self.store().messages.clear();
+    }
+
+    /// runs `f` on a message of a lowercase address, if it's there yet
+    pub(crate) fn with_message<R, F>(&self, address: &str, id: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(&StoredMessage) -> R,
+    {
+        let now = Instant::now();
+
+        self.store()
+            .messages
+            .iter()
+            .find(|stored| stored.address == address && stored.message.id == id && stored.visible_at <= now)
+            .map(f)
+    }
+}
+
+/// a message as it'd be delivered, without its attachments since it doesn't have their content
+impl From<Message> for MockMessage {
+    fn from(message: Message) -> Self {
+        let mut mock = MockMessage::new(message.from, message.subject).text(message.text_body).timestamp(message.timestamp);
+        mock.html_body = message.html_body;
+        mock
+    }
+}
+
+fn minimal_source(to: &str, from: &str, subject: &str, timestamp: DateTime<Utc>, body: &str) -> Vec<u8> {
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}",
+        from,
+        to,
+        subject,
+        timestamp.to_rfc2822(),
+        body.replace("\r\n", "\n").replace('\n', "\r\n"),
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use reqwest::StatusCode;
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use super::{MemoryProvider, MockMessage};
+    use crate::{random_string, Attachment, Domain, Message, Tempmail, TempmailError};
+
+    const ADDRESS: &str = "bob@1secmail.com";
+
+    fn inbox() -> (Arc<MemoryProvider>, Tempmail) {
+        let provider = Arc::new(MemoryProvider::new());
+        let inbox = provider.inbox("bob", Domain::SecMailCom);
+        (provider, inbox)
+    }
+
+    fn unavailable() -> TempmailError {
+        TempmailError::Status { status: StatusCode::BAD_GATEWAY, body: String::new(), retry_after: None }
+    }
+
+    #[tokio::test]
+    async fn reads_messages_and_attachments() {
+        let (provider, inbox) = inbox();
+        provider.mailbox().deliver(ADDRESS, MockMessage::new("alice@example.com", "first").text("hello"));
+        provider.mailbox().deliver(
+            ADDRESS,
+            MockMessage::new("alice@example.com", "second").attachment("a.txt", "text/plain", "data"),
+        );
+        provider.mailbox().deliver("eve@1secmail.com", MockMessage::new("alice@example.com", "not bob's"));
+
+        let raw_msgs = inbox.get_raw_messages().await.unwrap();
+        let subjects: Vec<&str> = raw_msgs.iter().map(|raw_msg| raw_msg.subject.as_str()).collect();
+        assert_eq!(subjects, ["second", "first"]);
+
+        let msg = inbox.read_raw_messsage(&raw_msgs[1]).await.unwrap();
+        assert_eq!(msg.text_body, "hello");
+        assert_eq!(inbox.get_messages().await.unwrap().len(), 2);
+
+        assert_eq!(inbox.get_attachment(2, "a.txt").await.unwrap(), b"data");
+        assert!(matches!(inbox.get_attachment(2, "b.txt").await, Err(TempmailError::NotFound)));
+        assert!(matches!(inbox.get_attachment(3, "a.txt").await, Err(TempmailError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_matching_message() {
+        let (provider, inbox) = inbox();
+        provider.mailbox().deliver(ADDRESS, MockMessage::new("alice@example.com", "newsletter"));
+        provider.mailbox().deliver_after(
+            ADDRESS,
+            MockMessage::new("alice@example.com", "your code").text("1234"),
+            Duration::from_millis(50),
+        );
+
+        let msg = inbox
+            .wait_for_message(|raw_msg| raw_msg.subject.contains("code"), Duration::from_secs(2), Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(msg.text_body, "1234");
+
+        let res = inbox
+            .wait_for_message(|raw_msg| raw_msg.subject == "never", Duration::from_millis(50), Duration::from_millis(10))
+            .await;
+        assert!(matches!(res, Err(TempmailError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn subscription_yields_old_then_new_messages() {
+        let (provider, inbox) = inbox();
+        provider.mailbox().deliver(ADDRESS, MockMessage::new("alice@example.com", "old"));
+
+        let mut stream = Box::pin(inbox.subscribe_with_interval(Duration::from_millis(10)));
+        assert_eq!(stream.next().await.unwrap().unwrap().subject, "old");
+
+        provider.fail_next(unavailable());
+        assert_eq!(stream.next().await.unwrap().unwrap_err().status(), Some(StatusCode::BAD_GATEWAY));
+
+        provider.mailbox().deliver(ADDRESS, MockMessage::new("alice@example.com", "new"));
+        assert_eq!(stream.next().await.unwrap().unwrap().subject, "new");
+    }
+
+    #[tokio::test]
+    async fn downloads_and_saves_attachments() {
+        let (provider, inbox) = inbox();
+        let id = provider.mailbox().deliver(
+            ADDRESS,
+            MockMessage::new("alice@example.com", "files")
+                .attachment("report.pdf", "application/pdf", vec![1, 2, 3])
+                .attachment("../report.pdf", "application/pdf", vec![4, 5]),
+        );
+        let msg = inbox.get_messages().await.unwrap().remove(0);
+
+        let bundle = msg.download_attachments(&inbox).await.unwrap();
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(bundle.get("report.pdf").unwrap().data, [1, 2, 3]);
+        assert_eq!(bundle.get("report (2).pdf").unwrap().data, [4, 5]);
+
+        let dir = std::env::temp_dir().join(format!("tempmail-{}", random_string(12)));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let path = inbox.save_attachment(id, &msg.attachments[1], &dir).await.unwrap();
+        assert_eq!(path, dir.join("report.pdf"));
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), [4, 5]);
+
+        let mut wrong_size = msg.attachments[0].clone();
+        wrong_size.size = 10;
+        let res = inbox.save_attachment(id, &wrong_size, &dir).await;
+        assert!(matches!(res, Err(TempmailError::SizeMismatch { expected: 10, actual: 3 })));
+        assert!(!path.exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn hooks_slow_down_and_fail_calls() {
+        let (provider, inbox) = inbox();
+        provider.mailbox().deliver(ADDRESS, MockMessage::new("alice@example.com", "hi"));
+
+        provider.set_latency(Duration::from_millis(50));
+        let started = Instant::now();
+        inbox.get_raw_messages().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        provider.set_latency(Duration::ZERO);
+
+        provider.fail_next(unavailable());
+        provider.fail_next(TempmailError::RateLimited { retry_after: None });
+        assert_eq!(inbox.get_raw_messages().await.unwrap_err().status(), Some(StatusCode::BAD_GATEWAY));
+        assert!(matches!(inbox.get_raw_messages().await, Err(TempmailError::RateLimited { .. })));
+        assert_eq!(inbox.get_raw_messages().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn delayed_messages_show_up_late() {
+        let (provider, inbox) = inbox();
+        let late = provider.mailbox().deliver_after(ADDRESS, MockMessage::new("alice@example.com", "late"), Duration::from_millis(50));
+        provider.mailbox().deliver(ADDRESS, MockMessage::new("alice@example.com", "early"));
+
+        let raw_msgs = inbox.get_raw_messages().await.unwrap();
+        assert_eq!(raw_msgs.len(), 1);
+        assert!(matches!(inbox.provider().read_message("bob", &Domain::SecMailCom, late).await, Err(TempmailError::NotFound)));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let ids: Vec<usize> = inbox.get_raw_messages().await.unwrap().iter().map(|raw_msg| raw_msg.id).collect();
+        assert_eq!(ids, [2, 1]);
+    }
+
+    #[tokio::test]
+    async fn delivers_messages_as_they_are_read() {
+        let (provider, inbox) = inbox();
+        let message = Message {
+            id: 42,
+            from: "alice@example.com".to_string(),
+            subject: "report".to_string(),
+            timestamp: chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            attachments: Vec::new(),
+            body: "<p>see attached</p>".to_string(),
+            text_body: "see attached".to_string(),
+            html_body: Some("<p>see attached</p>".to_string()),
+        };
+        let attachment = Attachment { filename: "a.csv".to_string(), content_type: "text/csv".to_string(), size: 0 };
+
+        let id = provider.deliver(ADDRESS, message.clone(), vec![(attachment, b"1,2\n".to_vec())]);
+        assert_eq!(id, 1);
+
+        let msg = inbox.get_messages().await.unwrap().remove(0);
+        assert_eq!((msg.from.as_str(), msg.subject.as_str(), msg.timestamp), ("alice@example.com", "report", message.timestamp));
+        assert_eq!((msg.text_body.as_str(), msg.html_body.as_deref()), ("see attached", Some("<p>see attached</p>")));
+        assert_eq!(msg.attachments[0].size, 4);
+        assert_eq!(inbox.get_attachment(id, "a.csv").await.unwrap(), b"1,2\n");
+    }
+}
@@ -0,0 +1,70 @@
+//! Backends a [`Tempmail`](crate::Tempmail) inbox can talk to
+
+use async_trait::async_trait;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{Domain, Message, RawMessage, TempmailError, TempmailResult};
+
+mod fallback;
+mod guerrilla;
+mod mailtm;
+#[cfg(feature = "memory")]
+mod memory;
+pub(crate) mod onesecmail;
+
+pub use fallback::{FallbackProvider, DEFAULT_HEALTH_TTL, DEFAULT_PROBE_TIMEOUT};
+pub use guerrilla::GuerrillaMail;
+pub use mailtm::MailTm;
+#[cfg(feature = "memory")]
+pub use memory::{MemoryProvider, MockMailbox, MockMessage};
+pub use onesecmail::OneSecMail;
+
+/// A disposable mail service
+///
+/// inboxes are identified by their username and domain, providers that need more than that
+/// (sessions, tokens...) keep track of it themselves
+#[async_trait]
+pub trait MailProvider: Send + Sync {
+    /// picks a new address and does whatever the service needs before it can receive mail on it
+    async fn create_address(&self) -> TempmailResult<(String, Domain)>;
+
+    /// the domains the service currently serves
+    async fn list_domains(&self) -> TempmailResult<Vec<Domain>>;
+
+    /// lists the messages in an inbox, without their content
+    async fn list_messages(&self, username: &str, domain: &Domain) -> TempmailResult<Vec<RawMessage>>;
+
+    /// reads a whole message
+    async fn read_message(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Message>;
+
+    /// downloads the content of an attachment
+    async fn download_attachment(&self, username: &str, domain: &Domain, id: usize, filename: &str) -> TempmailResult<Vec<u8>>;
+
+    /// streams the content of an attachment into a writer, returning how many bytes were written
+    ///
+    /// defaults to writing out what [`MailProvider::download_attachment`] returns, providers
+    /// that can stream override it
+    async fn download_attachment_to(
+        &self,
+        username: &str,
+        domain: &Domain,
+        id: usize,
+        filename: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> TempmailResult<u64> {
+        let data = self.download_attachment(username, domain, id, filename).await?;
+        writer.write_all(&data).await?;
+        writer.flush().await?;
+        Ok(data.len() as u64)
+    }
+
+    /// downloads the raw RFC 822 source of a message, not every service exposes it
+    async fn get_source(&self, _username: &str, _domain: &Domain, _id: usize) -> TempmailResult<Vec<u8>> {
+        Err(TempmailError::Unsupported("message source"))
+    }
+}
+
+/// key for providers keeping per inbox state
+pub(crate) fn address(username: &str, domain: &Domain) -> String {
+    format!("{}@{}", username, domain)
+}
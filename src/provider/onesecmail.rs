@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+use tokio::io::AsyncWrite;
+
+use crate::{random_username, Domain, MailProvider, Message, RawMessage, TempmailClient, TempmailResult};
+
+pub(crate) const DOMAINS_QUERY: &str = "action=getDomainList";
+
+pub(crate) fn messages_query(username: &str, domain: &Domain) -> String {
+    format!("action=getMessages&login={}&domain={}", username, domain)
+}
+
+pub(crate) fn read_query(username: &str, domain: &Domain, id: usize) -> String {
+    format!("action=readMessage&login={}&domain={}&id={}", username, domain, id)
+}
+
+pub(crate) fn download_query(username: &str, domain: &Domain, id: usize, filename: &str) -> String {
+    format!("action=download&login={}&domain={}&id={}&file={}", username, domain, id, encode(filename))
+}
+
+pub(crate) fn source_query(username: &str, domain: &Domain, id: usize) -> String {
+    format!("action=source&login={}&domain={}&id={}", username, domain, id)
+}
+
+/// percent-encodes a query value, filenames can contain anything
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The [1secmail](https://www.1secmail.com) api, which doesn't need any setup per inbox
+#[derive(Clone, Default)]
+pub struct OneSecMail {
+    client: TempmailClient,
+}
+
+impl OneSecMail {
+    pub fn new(client: TempmailClient) -> Self {
+        Self { client }
+    }
+
+    pub fn client(&self) -> &TempmailClient {
+        &self.client
+    }
+}
+
+#[async_trait]
+impl MailProvider for OneSecMail {
+    async fn create_address(&self) -> TempmailResult<(String, Domain)> {
+        Ok((random_username(), Domain::random()))
+    }
+
+    async fn list_domains(&self) -> TempmailResult<Vec<Domain>> {
+        let names: Vec<String> = self.client.reqjson(DOMAINS_QUERY).await?;
+        Ok(names.into_iter().map(Domain::from_name).collect())
+    }
+
+    async fn list_messages(&self, username: &str, domain: &Domain) -> TempmailResult<Vec<RawMessage>> {
+        self.client.reqjson(messages_query(username, domain)).await
+    }
+
+    async fn read_message(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Message> {
+        let msg: Message = self.client.reqjson(read_query(username, domain, id)).await?;
+        Ok(msg.normalized())
+    }
+
+    async fn download_attachment(&self, username: &str, domain: &Domain, id: usize, filename: &str) -> TempmailResult<Vec<u8>> {
+        self.client.reqbytes(download_query(username, domain, id, filename)).await
+    }
+
+    async fn download_attachment_to(
+        &self,
+        username: &str,
+        domain: &Domain,
+        id: usize,
+        filename: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> TempmailResult<u64> {
+        self.client.reqbytes_to(download_query(username, domain, id, filename), writer).await
+    }
+
+    async fn get_source(&self, username: &str, domain: &Domain, id: usize) -> TempmailResult<Vec<u8>> {
+        self.client.reqsource(source_query(username, domain, id)).await
+    }
+}
@@ -0,0 +1,190 @@
+use rand::Rng;
+use std::{fmt::Debug, future::Future, sync::Arc, time::Duration};
+
+use crate::{TempmailError, TempmailResult};
+
+/// How a client retries api calls that failed for a reason that's likely to go away
+///
+/// the delay before each retry doubles from `base_delay` up to `max_delay`, and with jitter
+/// is a random value between half of it and all of it. when the api sends a `Retry-After`,
+/// with a 429 or a 5xx, that's waited out instead, up to `max_delay` too
+///
+/// the default makes 3 attempts, starting at 500ms with jitter, and retries what
+/// [`RetryPolicy::is_transient`] deems transient. [`RetryPolicy::none`] turns retrying off
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    retryable: Arc<dyn Fn(&TempmailError) -> bool + Send + Sync>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            retryable: Arc::new(Self::is_transient),
+        }
+    }
+}
+
+impl Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_delay", &self.base_delay)
+            .field("max_delay", &self.max_delay)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a policy that never retries
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// how many times a call is made at most, the first one included
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// the delay before the first retry
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// the longest delay between two attempts, `Retry-After` included
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// randomizes delays, so clients failing together don't retry together
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// decides which errors are retried, replacing [`RetryPolicy::is_transient`]
+    pub fn retry_if<F>(mut self, retryable: F) -> Self
+    where
+        F: Fn(&TempmailError) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Arc::new(retryable);
+        self
+    }
+
+    pub fn get_max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// the default retry predicate, [`TempmailError::is_transient`]
+    pub fn is_transient(err: &TempmailError) -> bool {
+        err.is_transient()
+    }
+
+    /// how long to wait after the given failed attempt, counting from 1
+    pub(crate) fn delay(&self, attempt: u32, err: &TempmailError) -> Duration {
+        if let Some(retry_after) = err.retry_after() {
+            return retry_after.min(self.max_delay);
+        }
+
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+
+        match self.jitter {
+            true => delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0)),
+            false => delay,
+        }
+    }
+
+    /// whether another attempt should follow the given failed one
+    fn should_retry(&self, attempt: u32, err: &TempmailError) -> bool {
+        attempt < self.max_attempts && (self.retryable)(err)
+    }
+
+    /// runs an api call, retrying it according to the policy
+    pub(crate) async fn run<T, F, Fut>(&self, mut call: F) -> TempmailResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = TempmailResult<T>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            match call().await {
+                Err(err) if self.should_retry(attempt, &err) => {
+                    tokio::time::sleep(self.delay(attempt, &err)).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// blocking version of [`RetryPolicy::run`]
+    #[cfg(feature = "blocking")]
+    pub(crate) fn run_blocking<T, F>(&self, mut call: F) -> TempmailResult<T>
+    where
+        F: FnMut() -> TempmailResult<T>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            match call() {
+                Err(err) if self.should_retry(attempt, &err) => {
+                    std::thread::sleep(self.delay(attempt, &err));
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+    use crate::TempmailError;
+
+    fn unavailable(retry_after: Option<Duration>) -> TempmailError {
+        TempmailError::Status { status: StatusCode::SERVICE_UNAVAILABLE, body: String::new(), retry_after }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_max() {
+        let policy = RetryPolicy::new()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(350))
+            .jitter(false);
+        let delays: Vec<Duration> = (1..=4).map(|attempt| policy.delay(attempt, &unavailable(None))).collect();
+        assert_eq!(delays, [100, 200, 350, 350].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn waits_out_retry_after_up_to_the_max() {
+        let policy = RetryPolicy::new().max_delay(Duration::from_secs(30));
+        assert_eq!(policy.delay(1, &unavailable(Some(Duration::from_secs(3)))), Duration::from_secs(3));
+        assert_eq!(policy.delay(1, &unavailable(Some(Duration::from_secs(3600)))), Duration::from_secs(30));
+
+        let rate_limited = TempmailError::RateLimited { retry_after: Some(Duration::from_secs(2)) };
+        assert_eq!(policy.delay(3, &rate_limited), Duration::from_secs(2));
+    }
+}
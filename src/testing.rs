@@ -0,0 +1,274 @@
+//! An in-process stand-in for the 1secmail api, to test code using [`Tempmail`] without network
+//!
+//! a [`MockServer`] serves the same query api as 1secmail (`getDomainList`, `getMessages`,
+//! `readMessage`, `download` and the `/mailbox/` source) out of a [`MockMailbox`], which tests
+//! fill with [`MockMailbox::deliver`]. the inboxes and clients it hands out are the regular ones,
+//! only pointed at the local server
+//!
+//! a [`MailTmServer`] does the same for the [`MailTm`](crate::provider::MailTm) provider,
+//! answering with synthetic responses shaped like mail.tm's
+//!
+//! to skip http altogether, a [`MemoryProvider`](crate::provider::MemoryProvider) reads a
+//! [`MockMailbox`] directly and can make the inbox slow or flaky. it only needs the `memory`
+//! feature, which `testing` turns on
+//!
+//! with the `smtp-sink` feature, an `SmtpSink` takes real SMTP from the code under test and
+//! delivers it into the same mailbox
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, StatusCode,
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::{SocketAddr, TcpListener},
+    thread::JoinHandle,
+    time::Duration,
+};
+use futures::future::{self, FutureExt, Shared};
+use tokio::sync::oneshot;
+
+mod mailtm;
+#[cfg(feature = "smtp-sink")]
+mod smtp;
+
+pub use mailtm::{MailTmServer, MAIL_TM_ATTACHMENT};
+
+#[cfg(feature = "smtp-sink")]
+pub use smtp::{SmtpSink, MAX_MESSAGE_SIZE};
+
+pub use crate::provider::{MockMailbox, MockMessage};
+
+use crate::{client::NOT_FOUND_BODY, random_username, Domain, Message, Tempmail, TempmailClient, TempmailResult};
+
+/// the date format of the api
+const API_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// how long a stopped server waits for open connections before dropping them
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
+
+/// fires when a background server should stop
+pub(crate) type Stopped = Shared<oneshot::Receiver<()>>;
+
+/// A local http server speaking the 1secmail api, stopped when dropped
+///
+/// it runs on its own thread and runtime, so it works from sync tests, async tests and with
+/// the blocking client alike
+pub struct MockServer {
+    addr: SocketAddr,
+    mailbox: MockMailbox,
+    _background: Background,
+}
+
+/// A server running on its own thread and runtime, shut down and joined when dropped
+pub(crate) struct Background {
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// starts a server on a random local port with an empty mailbox
+    pub fn start() -> TempmailResult<Self> {
+        Self::with_mailbox(MockMailbox::new())
+    }
+
+    /// starts a server on a random local port serving the given mailbox
+    pub fn with_mailbox(mailbox: MockMailbox) -> TempmailResult<Self> {
+        let listener = local_listener()?;
+        let addr = listener.local_addr()?;
+        let served = mailbox.clone();
+
+        let background = Background::spawn(move |stopped| async move {
+            let make_service = make_service_fn(move |_| {
+                let mailbox = served.clone();
+                async move { Ok::<_, Infallible>(service_fn(move |req| handle(mailbox.clone(), req))) }
+            });
+
+            let Ok(server) = hyper::Server::from_tcp(listener) else {
+                return;
+            };
+
+            let _ = server
+                .serve(make_service)
+                .with_graceful_shutdown(async {
+                    let _ = stopped.await;
+                })
+                .await;
+        })?;
+
+        Ok(Self { addr, mailbox, _background: background })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn mailbox(&self) -> &MockMailbox {
+        &self.mailbox
+    }
+
+    /// the url to use as a client's base url, like `http://127.0.0.1:12345/api/v1/`
+    pub fn base_url(&self) -> String {
+        format!("http://{}/api/v1/", self.addr)
+    }
+
+    /// a client talking to this server
+    pub fn client(&self) -> TempmailClient {
+        TempmailClient::default().with_base_url(self.base_url())
+    }
+
+    /// a blocking client talking to this server, like any blocking client it can't be created
+    /// or dropped inside an async runtime
+    #[cfg(feature = "blocking")]
+    pub fn blocking_client(&self) -> TempmailResult<crate::blocking::TempmailClient> {
+        TempmailClient::builder().base_url(self.base_url()).build_blocking()
+    }
+
+    /// an inbox on this server
+    pub fn inbox<U>(&self, username: U, domain: Domain) -> Tempmail
+    where
+        U: Into<String>,
+    {
+        self.client().inbox(username, Some(domain))
+    }
+
+    /// an inbox with a random username on one of the mailbox's domains
+    pub fn random_inbox(&self) -> Tempmail {
+        let domain = Domain::pick(self.mailbox.domains()).unwrap_or_default();
+        self.inbox(random_username(), domain)
+    }
+}
+
+impl Background {
+    /// runs the future `serve` makes on a new thread, it should return once `stopped` fires
+    ///
+    /// it's dropped [`SHUTDOWN_GRACE`] after that anyway: a graceful hyper shutdown waits
+    /// forever on connections that never got a request, which reqwest's pool leaves behind
+    /// when requests run concurrently
+    pub(crate) fn spawn<S, F>(serve: S) -> TempmailResult<Self>
+    where
+        S: FnOnce(Stopped) -> F + Send + 'static,
+        F: Future<Output = ()>,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let (shutdown, stopped) = oneshot::channel();
+        let stopped = stopped.shared();
+
+        let thread = std::thread::spawn(move || {
+            runtime.block_on(async move {
+                let grace = stopped.clone().then(|_| tokio::time::sleep(SHUTDOWN_GRACE));
+                future::select(Box::pin(serve(stopped)), Box::pin(grace)).await;
+            })
+        });
+
+        Ok(Self { shutdown: Some(shutdown), thread: Some(thread) })
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// binds a random local port, ready to be handed to tokio
+pub(crate) fn local_listener() -> TempmailResult<TcpListener> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+async fn handle(mailbox: MockMailbox, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let Ok(url) = reqwest::Url::parse(&format!("http://mock{}", req.uri())) else {
+        return Ok(text(StatusCode::BAD_REQUEST, "Bad request"));
+    };
+
+    let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let param = |name: &str| query.get(name).map(String::as_str).unwrap_or_default();
+
+    let address = format!("{}@{}", param("login"), param("domain")).to_ascii_lowercase();
+    let id = param("id").parse::<usize>().unwrap_or_default();
+
+    let res = match (url.path().trim_end_matches('/'), param("action")) {
+        ("/api/v1", "getDomainList") => {
+            let domains: Vec<String> = mailbox.domains().iter().map(Domain::to_string).collect();
+            json_response(json!(domains))
+        }
+        ("/api/v1", "getMessages") => {
+            let mut messages = mailbox.messages(&address);
+            messages.reverse();
+
+            let messages: Vec<_> = messages.iter().map(raw_message_json).collect();
+            json_response(json!(messages))
+        }
+        ("/api/v1", "readMessage") => match mailbox.with_message(&address, id, |stored| message_json(&stored.message)) {
+            Some(message) => json_response(message),
+            None => text(StatusCode::OK, NOT_FOUND_BODY),
+        },
+        ("/api/v1", "download") => {
+            let data = mailbox.with_message(&address, id, |stored| stored.attachments.get(param("file")).cloned());
+
+            match data.flatten() {
+                Some(data) => Response::new(Body::from(data)),
+                None => text(StatusCode::NOT_FOUND, NOT_FOUND_BODY),
+            }
+        }
+        ("/mailbox", "source") => match mailbox.with_message(&address, id, |stored| stored.source.clone()) {
+            Some(source) => Response::new(Body::from(source)),
+            None => text(StatusCode::OK, NOT_FOUND_BODY),
+        },
+        _ => text(StatusCode::BAD_REQUEST, "Wrong action"),
+    };
+
+    Ok(res)
+}
+
+fn text(status: StatusCode, body: &'static str) -> Response<Body> {
+    let mut res = Response::new(Body::from(body));
+    *res.status_mut() = status;
+    res
+}
+
+fn json_response(value: serde_json::Value) -> Response<Body> {
+    let mut res = Response::new(Body::from(value.to_string()));
+    res.headers_mut()
+        .insert(hyper::header::CONTENT_TYPE, hyper::header::HeaderValue::from_static("application/json"));
+    res
+}
+
+fn raw_message_json(msg: &Message) -> serde_json::Value {
+    json!({
+        "id": msg.id,
+        "from": msg.from,
+        "subject": msg.subject,
+        "date": msg.timestamp.format(API_DATE_FORMAT).to_string(),
+    })
+}
+
+fn message_json(msg: &Message) -> serde_json::Value {
+    let attachments: Vec<_> = msg
+        .attachments
+        .iter()
+        .map(|att| json!({ "filename": att.filename, "contentType": att.content_type, "size": att.size }))
+        .collect();
+
+    json!({
+        "id": msg.id,
+        "from": msg.from,
+        "subject": msg.subject,
+        "date": msg.timestamp.format(API_DATE_FORMAT).to_string(),
+        "attachments": attachments,
+        "body": msg.body,
+        "textBody": msg.text_body,
+        "htmlBody": msg.html_body.clone().unwrap_or_default(),
+    })
+}
@@ -0,0 +1,242 @@
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::{local_listener, Background};
+use crate::{provider::MailTm, TempmailClient, TempmailResult};
+
+/// synthetic responses shaped like api.mail.tm's, the message list spanning two pages in the
+/// `hydra:member` envelope
+const DOMAINS: &str = include_str!("fixtures/mailtm/domains.json");
+const ACCOUNT: &str = include_str!("fixtures/mailtm/account.json");
+const TOKEN: &str = include_str!("fixtures/mailtm/token.json");
+const MESSAGES_PAGE_1: &str = include_str!("fixtures/mailtm/messages_page1.json");
+const MESSAGES_PAGE_2: &str = include_str!("fixtures/mailtm/messages_page2.json");
+const MESSAGE: &str = include_str!("fixtures/mailtm/message.json");
+const SOURCE: &str = include_str!("fixtures/mailtm/source.json");
+
+/// the content of the attachment of the readable message
+pub const MAIL_TM_ATTACHMENT: &[u8] = b"invoice #1234 paid\n";
+
+/// A local http server answering like the mail.tm api with synthetic responses, stopped when dropped
+///
+/// every account is served the same made up inbox of 31 messages, of which only the newest
+/// can be read. accounts and tokens are checked like the api does: logging in needs the
+/// password the account was created with, and authenticated requests need a token that
+/// [`MailTmServer::expire_tokens`] hasn't expired
+pub struct MailTmServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MailTmState>>,
+    _background: Background,
+}
+
+#[derive(Default)]
+struct MailTmState {
+    /// passwords by address
+    accounts: HashMap<String, String>,
+    issued: usize,
+    tokens: HashSet<String>,
+    /// request line prefixes and the status to answer them with
+    failures: VecDeque<(String, StatusCode)>,
+    requests: Vec<String>,
+}
+
+impl MailTmServer {
+    /// starts a server on a random local port
+    pub fn start() -> TempmailResult<Self> {
+        let listener = local_listener()?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(MailTmState::default()));
+        let served = state.clone();
+
+        let background = Background::spawn(move |stopped| async move {
+            let make_service = make_service_fn(move |_| {
+                let state = served.clone();
+                async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+            });
+
+            let Ok(server) = hyper::Server::from_tcp(listener) else {
+                return;
+            };
+
+            let _ = server
+                .serve(make_service)
+                .with_graceful_shutdown(async {
+                    let _ = stopped.await;
+                })
+                .await;
+        })?;
+
+        Ok(Self { addr, state, _background: background })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// the url to give [`MailTm::with_base_url`], like `http://127.0.0.1:12345`
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// a provider talking to this server
+    pub fn provider(&self, client: TempmailClient) -> MailTm {
+        MailTm::with_base_url(client, self.base_url())
+    }
+
+    fn state(&self) -> MutexGuard<'_, MailTmState> {
+        lock(&self.state)
+    }
+
+    /// makes every token issued so far invalid, like they'd be once they expire
+    pub fn expire_tokens(&self) {
+        self.state().tokens.clear();
+    }
+
+    /// answers the next request starting like `request`, for example `POST /accounts`, with
+    /// an empty response of the given status. calling it again queues more failures
+    pub fn fail_next<R>(&self, request: R, status: StatusCode)
+    where
+        R: Into<String>,
+    {
+        self.state().failures.push_back((request.into(), status));
+    }
+
+    /// every request received so far, like `POST /token` or `GET /messages?page=1`
+    pub fn requests(&self) -> Vec<String> {
+        self.state().requests.clone()
+    }
+}
+
+fn lock(state: &Mutex<MailTmState>) -> MutexGuard<'_, MailTmState> {
+    state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+async fn handle(state: Arc<Mutex<MailTmState>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let path_and_query = req.uri().path_and_query().map(|pq| pq.to_string()).unwrap_or_default();
+
+    let bearer = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    let body: Value = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) if !body.is_empty() => serde_json::from_slice(&body).unwrap_or_default(),
+        _ => Value::Null,
+    };
+
+    let request = format!("{} {}", method, path_and_query);
+    let mut state = lock(&state);
+    state.requests.push(request.clone());
+
+    let failure = state.failures.iter().position(|(prefix, _)| request.starts_with(prefix.as_str()));
+
+    if let Some((_, status)) = failure.and_then(|idx| state.failures.remove(idx)) {
+        return Ok(status_response(status, Body::empty()));
+    }
+
+    let address = body["address"].as_str().unwrap_or_default().to_ascii_lowercase();
+    let password = body["password"].as_str().unwrap_or_default().to_string();
+
+    let res = match (method, path.as_str()) {
+        (Method::GET, "/domains") => fixture(DOMAINS, |_| {}),
+        (Method::POST, "/accounts") if state.accounts.contains_key(&address) => {
+            violation("address: This value is already used.")
+        }
+        (Method::POST, "/accounts") if address.is_empty() || password.is_empty() => {
+            violation("address: This value should not be blank.")
+        }
+        (Method::POST, "/accounts") => {
+            state.accounts.insert(address.clone(), password);
+
+            let mut res = fixture(ACCOUNT, |account| account["address"] = json!(address));
+            *res.status_mut() = StatusCode::CREATED;
+            res
+        }
+        (Method::POST, "/token") if state.accounts.get(&address) == Some(&password) => {
+            state.issued += 1;
+            let token = format!("mock-token-{}", state.issued);
+            state.tokens.insert(token.clone());
+
+            fixture(TOKEN, |response| response["token"] = json!(token))
+        }
+        (Method::POST, "/token") => error(StatusCode::UNAUTHORIZED, "Invalid credentials."),
+        (Method::GET, _) if !bearer.is_some_and(|token| state.tokens.contains(&token)) => {
+            error(StatusCode::UNAUTHORIZED, "Expired JWT Token")
+        }
+        (Method::GET, "/messages") => match path_and_query.rsplit_once("page=").map(|(_, page)| page) {
+            None | Some("1") => fixture(MESSAGES_PAGE_1, |_| {}),
+            Some("2") => fixture(MESSAGES_PAGE_2, |_| {}),
+            Some(_) => fixture(MESSAGES_PAGE_2, |page| page["hydra:member"] = json!([])),
+        },
+        (Method::GET, path) => {
+            let id = readable_id();
+
+            if path == format!("/messages/{}", id) {
+                fixture(MESSAGE, |_| {})
+            } else if path == format!("/sources/{}", id) {
+                fixture(SOURCE, |_| {})
+            } else if path == format!("/messages/{}/attachment/ATTACH000001", id) {
+                Response::new(Body::from(MAIL_TM_ATTACHMENT))
+            } else {
+                error(StatusCode::NOT_FOUND, "Not Found")
+            }
+        }
+        _ => error(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"),
+    };
+
+    Ok(res)
+}
+
+/// the id of the only message that can be read
+fn readable_id() -> String {
+    serde_json::from_str::<Value>(MESSAGE).unwrap_or_default()["id"].as_str().unwrap_or_default().to_string()
+}
+
+/// a fixture response, `edit` filling in what depends on the request
+fn fixture<E>(fixture: &str, edit: E) -> Response<Body>
+where
+    E: FnOnce(&mut Value),
+{
+    let mut value: Value = serde_json::from_str(fixture).unwrap_or_default();
+    edit(&mut value);
+
+    let mut res = Response::new(Body::from(value.to_string()));
+    res.headers_mut()
+        .insert(hyper::header::CONTENT_TYPE, hyper::header::HeaderValue::from_static("application/ld+json"));
+    res
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    let body = json!({ "code": status.as_u16(), "message": message });
+    status_response(status, Body::from(body.to_string()))
+}
+
+/// the api's validation error, sent when creating an account fails
+fn violation(detail: &str) -> Response<Body> {
+    let body = json!({
+        "@type": "ConstraintViolationList",
+        "hydra:title": "An error occurred",
+        "hydra:description": detail,
+        "detail": detail,
+    });
+    status_response(StatusCode::UNPROCESSABLE_ENTITY, Body::from(body.to_string()))
+}
+
+fn status_response(status: StatusCode, body: Body) -> Response<Body> {
+    let mut res = Response::new(body);
+    *res.status_mut() = status;
+    res
+}
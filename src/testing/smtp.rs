@@ -0,0 +1,487 @@
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+};
+
+use super::{local_listener, Background, MockMailbox, MockServer};
+use crate::TempmailResult;
+
+/// the biggest message the sink takes, announced with `SIZE`
+pub const MAX_MESSAGE_SIZE: usize = 25 * 1024 * 1024;
+
+/// the longest command line, CRLF included, as RFC 5321 allows
+const MAX_COMMAND_LINE: usize = 512;
+
+/// A local SMTP server delivering everything it receives into a [`MockMailbox`], stopped when dropped
+///
+/// it takes mail for any address without authentication (though it accepts any `AUTH PLAIN`
+/// or `AUTH LOGIN` credentials, for clients that insist) and without TLS, so the app under
+/// test has to be pointed at it with plain SMTP. each recipient gets its own copy, parsed
+/// with [`MockMailbox::deliver_raw`]. command lines longer than the 512 octets RFC 5321 allows
+/// are answered with `500 line too long`
+pub struct SmtpSink {
+    addr: SocketAddr,
+    mailbox: MockMailbox,
+    _background: Background,
+}
+
+/// how reading a line went
+enum Line {
+    Complete,
+    /// the line was over the limit, and was skipped
+    TooLong,
+    /// the connection dropped, possibly halfway through the line
+    Closed,
+}
+
+/// the envelope of the mail being received
+#[derive(Default)]
+struct Transaction {
+    from: Option<String>,
+    recipients: Vec<String>,
+}
+
+impl SmtpSink {
+    /// starts a sink on a random local port, delivering into the given mailbox
+    pub fn start(mailbox: MockMailbox) -> TempmailResult<Self> {
+        let listener = local_listener()?;
+        let addr = listener.local_addr()?;
+        let delivered = mailbox.clone();
+
+        let background = Background::spawn(move |mut stopped| async move {
+            let Ok(listener) = tokio::net::TcpListener::from_std(listener) else {
+                return;
+            };
+
+            loop {
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    accepted = listener.accept() => {
+                        if let Ok((stream, _)) = accepted {
+                            tokio::spawn(session(stream, delivered.clone()));
+                        }
+                    }
+                }
+            }
+        })?;
+
+        Ok(Self { addr, mailbox, _background: background })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// the port to point the app's SMTP settings at, the host being `127.0.0.1`
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    pub fn mailbox(&self) -> &MockMailbox {
+        &self.mailbox
+    }
+}
+
+impl MockServer {
+    /// starts an SMTP sink delivering into this server's mailbox, so mail sent to it can be
+    /// read through the server's inboxes
+    pub fn smtp_sink(&self) -> TempmailResult<SmtpSink> {
+        SmtpSink::start(self.mailbox().clone())
+    }
+}
+
+async fn session(stream: TcpStream, mailbox: MockMailbox) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read);
+    let mut transaction = Transaction::default();
+    let mut line = Vec::new();
+
+    if reply(&mut write, "220 localhost tempmail smtp sink ready").await.is_err() {
+        return;
+    }
+
+    loop {
+        match read_line(&mut lines, &mut line, MAX_COMMAND_LINE).await {
+            Ok(Line::Complete) => {}
+            Ok(Line::TooLong) => {
+                if reply(&mut write, "500 line too long").await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            Ok(Line::Closed) | Err(_) => return,
+        }
+
+        let command = String::from_utf8_lossy(&line);
+        let command = command.trim_end();
+        let (verb, arg) = command.split_once(' ').unwrap_or((command, ""));
+
+        let response = match verb.to_ascii_uppercase().as_str() {
+            "HELO" => {
+                transaction = Transaction::default();
+                "250 localhost".to_string()
+            }
+            "EHLO" => {
+                transaction = Transaction::default();
+                format!("250-localhost\r\n250-8BITMIME\r\n250-SMTPUTF8\r\n250-AUTH PLAIN LOGIN\r\n250 SIZE {}", MAX_MESSAGE_SIZE)
+            }
+            "MAIL" => match path(arg, "FROM:") {
+                Some(from) => {
+                    transaction = Transaction { from: Some(from), recipients: Vec::new() };
+                    "250 OK".to_string()
+                }
+                None => "501 Syntax: MAIL FROM:<address>".to_string(),
+            },
+            "RCPT" => match (transaction.from.is_some(), path(arg, "TO:")) {
+                (false, _) => "503 MAIL first".to_string(),
+                (true, Some(to)) if to.contains('@') => {
+                    transaction.recipients.push(to);
+                    "250 OK".to_string()
+                }
+                (true, _) => "501 Syntax: RCPT TO:<address>".to_string(),
+            },
+            "DATA" if transaction.recipients.is_empty() => "503 RCPT first".to_string(),
+            "DATA" => {
+                if reply(&mut write, "354 End data with <CR><LF>.<CR><LF>").await.is_err() {
+                    return;
+                }
+
+                let Some(data) = read_data(&mut lines).await else {
+                    return;
+                };
+
+                let transaction = std::mem::take(&mut transaction);
+
+                match data {
+                    Some(data) => {
+                        let ids: Vec<String> = transaction
+                            .recipients
+                            .iter()
+                            .map(|to| mailbox.deliver_raw(to, &with_return_path(&transaction, &data)).to_string())
+                            .collect();
+                        format!("250 OK queued as {}", ids.join(","))
+                    }
+                    None => "552 Message size exceeds fixed maximum message size".to_string(),
+                }
+            }
+            "AUTH" => match auth(arg, &mut lines, &mut write).await {
+                Some(response) => response.to_string(),
+                None => return,
+            },
+            "RSET" => {
+                transaction = Transaction::default();
+                "250 OK".to_string()
+            }
+            "NOOP" => "250 OK".to_string(),
+            "VRFY" => "252 Cannot verify, but will accept".to_string(),
+            "QUIT" => {
+                let _ = reply(&mut write, "221 Bye").await;
+                return;
+            }
+            "STARTTLS" => "502 TLS not supported".to_string(),
+            _ => "500 Command not recognized".to_string(),
+        };
+
+        if reply(&mut write, &response).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// reads a line into `line`, without buffering more than `max` bytes of it
+async fn read_line<R>(reader: &mut R, line: &mut Vec<u8>, max: usize) -> std::io::Result<Line>
+where
+    R: AsyncBufRead + Unpin,
+{
+    line.clear();
+    let mut too_long = false;
+
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok(Line::Closed);
+        }
+
+        let (len, done) = match buf.iter().position(|&b| b == b'\n') {
+            Some(idx) => (idx + 1, true),
+            None => (buf.len(), false),
+        };
+
+        if too_long || line.len() + len > max {
+            too_long = true;
+            line.clear();
+        } else {
+            line.extend_from_slice(&buf[..len]);
+        }
+
+        reader.consume(len);
+
+        if done {
+            return Ok(if too_long { Line::TooLong } else { Line::Complete });
+        }
+    }
+}
+
+async fn reply(write: &mut OwnedWriteHalf, response: &str) -> std::io::Result<()> {
+    write.write_all(response.as_bytes()).await?;
+    write.write_all(b"\r\n").await
+}
+
+/// parses `FROM:<address> PARAMS` into the address, `<>` being the empty one
+fn path(arg: &str, prefix: &str) -> Option<String> {
+    let arg = arg.trim();
+    let rest = arg.get(..prefix.len()).filter(|head| head.eq_ignore_ascii_case(prefix))?;
+    let rest = arg[rest.len()..].trim_start();
+
+    let address = match rest.strip_prefix('<') {
+        Some(rest) => rest.split_once('>')?.0,
+        None => rest.split_whitespace().next().unwrap_or_default(),
+    };
+
+    Some(address.trim().to_string())
+}
+
+/// reads the message until the lone `.` line, undoing the dot stuffing
+///
+/// `None` when the connection dropped, `Some(None)` when the message was too big
+async fn read_data(lines: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let mut too_big = false;
+    let mut line = Vec::new();
+
+    loop {
+        let read = read_line(lines, &mut line, MAX_MESSAGE_SIZE).await.ok()?;
+
+        if matches!(read, Line::Closed) {
+            return None;
+        }
+
+        if line == b".\r\n" || line == b".\n" {
+            break;
+        }
+
+        let line = line.strip_prefix(b".").unwrap_or(&line);
+
+        if matches!(read, Line::TooLong) || data.len() + line.len() > MAX_MESSAGE_SIZE {
+            too_big = true;
+        } else if !too_big {
+            data.extend_from_slice(line);
+        }
+    }
+
+    Some((!too_big).then_some(data))
+}
+
+/// goes through `AUTH PLAIN` or `AUTH LOGIN`, accepting any credentials
+///
+/// returns the final response, `None` when the connection dropped
+async fn auth(
+    arg: &str,
+    lines: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    write: &mut OwnedWriteHalf,
+) -> Option<&'static str> {
+    let mut parts = arg.split_whitespace();
+    let mechanism = parts.next().unwrap_or_default().to_ascii_uppercase();
+    let initial = parts.next();
+
+    // the prompts are `Username:` and `Password:` in base64, asked for until answered
+    let prompts: &[&str] = match (mechanism.as_str(), initial) {
+        ("PLAIN", Some(_)) => &[],
+        ("PLAIN", None) => &[""],
+        ("LOGIN", Some(_)) => &["UGFzc3dvcmQ6"],
+        ("LOGIN", None) => &["VXNlcm5hbWU6", "UGFzc3dvcmQ6"],
+        _ => return Some("504 Unrecognized authentication type"),
+    };
+
+    let mut line = Vec::new();
+
+    for prompt in prompts {
+        reply(write, &format!("334 {}", prompt)).await.ok()?;
+
+        match read_line(lines, &mut line, MAX_COMMAND_LINE).await.ok()? {
+            Line::Complete => {}
+            Line::TooLong => return Some("500 line too long"),
+            Line::Closed => return None,
+        }
+    }
+
+    Some("235 Authentication successful")
+}
+
+/// prepends the envelope sender like a delivering server would
+fn with_return_path(transaction: &Transaction, data: &[u8]) -> Vec<u8> {
+    let mut message = format!("Return-Path: <{}>\r\n", transaction.from.as_deref().unwrap_or_default()).into_bytes();
+    message.extend_from_slice(data);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpStream,
+        },
+    };
+
+    use super::{SmtpSink, MAX_MESSAGE_SIZE};
+    use crate::testing::MockMailbox;
+
+    struct Client {
+        lines: BufReader<OwnedReadHalf>,
+        write: OwnedWriteHalf,
+    }
+
+    impl Client {
+        async fn connect(sink: &SmtpSink) -> Self {
+            let (read, write) = TcpStream::connect(sink.addr()).await.unwrap().into_split();
+            let mut client = Self { lines: BufReader::new(read), write };
+            assert!(client.response().await.starts_with("220 "));
+            client
+        }
+
+        /// reads a whole, possibly multiline, response
+        async fn response(&mut self) -> String {
+            let mut response = String::new();
+
+            loop {
+                let mut line = String::new();
+                self.lines.read_line(&mut line).await.unwrap();
+                response.push_str(&line);
+
+                if line.len() < 4 || line.as_bytes()[3] != b'-' {
+                    return response.trim_end().to_string();
+                }
+            }
+        }
+
+        async fn send(&mut self, line: &str) -> String {
+            self.write.write_all(format!("{}\r\n", line).as_bytes()).await.unwrap();
+            self.response().await
+        }
+    }
+
+    fn start() -> (SmtpSink, MockMailbox) {
+        let mailbox = MockMailbox::new();
+        (SmtpSink::start(mailbox.clone()).unwrap(), mailbox)
+    }
+
+    fn source(mailbox: &MockMailbox, address: &str, id: usize) -> String {
+        let source = mailbox.with_message(address, id, |stored| stored.source.clone()).unwrap();
+        String::from_utf8(source).unwrap()
+    }
+
+    #[tokio::test]
+    async fn delivers_to_every_recipient() {
+        let (sink, mailbox) = start();
+        let mut client = Client::connect(&sink).await;
+
+        let ehlo = client.send("EHLO client.example").await;
+        assert!(ehlo.contains("250-AUTH PLAIN LOGIN"));
+        assert!(ehlo.ends_with(&format!("250 SIZE {}", MAX_MESSAGE_SIZE)));
+
+        assert!(client.send("RCPT TO:<bob@1secmail.com>").await.starts_with("503 "));
+        assert!(client.send("MAIL FROM:<alice@example.com> SIZE=100").await.starts_with("250 "));
+        assert!(client.send("RCPT TO:<bob@1secmail.com>").await.starts_with("250 "));
+        assert!(client.send("RCPT TO:<Eve@1secmail.com>").await.starts_with("250 "));
+        assert!(client.send("RCPT TO:nobody").await.starts_with("501 "));
+        assert!(client.send("DATA").await.starts_with("354 "));
+
+        let data = "From: alice@example.com\r\nSubject: hi\r\n\r\nhello\r\n.";
+        assert_eq!(client.send(data).await, "250 OK queued as 1,2");
+        assert!(client.send("QUIT").await.starts_with("221 "));
+
+        for (id, address) in [(1, "bob@1secmail.com"), (2, "eve@1secmail.com")] {
+            let messages = mailbox.messages(address);
+            assert_eq!(messages.len(), 1);
+            assert_eq!((messages[0].from.as_str(), messages[0].subject.as_str()), ("alice@example.com", "hi"));
+            assert!(source(&mailbox, address, id).starts_with("Return-Path: <alice@example.com>\r\nFrom:"));
+        }
+    }
+
+    #[tokio::test]
+    async fn undoes_dot_stuffing() {
+        let (sink, mailbox) = start();
+        let mut client = Client::connect(&sink).await;
+
+        client.send("HELO client.example").await;
+        client.send("MAIL FROM:<>").await;
+        client.send("RCPT TO:<bob@1secmail.com>").await;
+        client.send("DATA").await;
+        let response = client.send("Subject: dots\r\n\r\n..leading dot\r\n...\r\nend\r\n.").await;
+        assert!(response.starts_with("250 "));
+
+        let source = source(&mailbox, "bob@1secmail.com", 1);
+        assert!(source.starts_with("Return-Path: <>\r\n"));
+        assert!(source.ends_with("\r\n\r\n.leading dot\r\n..\r\nend\r\n"));
+    }
+
+    #[tokio::test]
+    async fn refuses_messages_over_the_size_limit() {
+        let (sink, mailbox) = start();
+        let mut client = Client::connect(&sink).await;
+
+        client.send("EHLO client.example").await;
+        client.send("MAIL FROM:<alice@example.com>").await;
+        client.send("RCPT TO:<bob@1secmail.com>").await;
+        client.send("DATA").await;
+
+        let line = format!("{}\r\n", "x".repeat(1023));
+        for _ in 0..=MAX_MESSAGE_SIZE / line.len() {
+            client.write.write_all(line.as_bytes()).await.unwrap();
+        }
+        assert!(client.send(".").await.starts_with("552 "));
+        assert!(mailbox.messages("bob@1secmail.com").is_empty());
+
+        // the session goes on, and the envelope was reset
+        assert!(client.send("RCPT TO:<bob@1secmail.com>").await.starts_with("503 "));
+    }
+
+    #[tokio::test]
+    async fn answers_overlong_command_lines() {
+        let (sink, _mailbox) = start();
+        let mut client = Client::connect(&sink).await;
+
+        let long = format!("MAIL FROM:<{}@example.com>", "a".repeat(600));
+        assert_eq!(client.send(&long).await, "500 line too long");
+        assert_eq!(client.send(&"x".repeat(100_000)).await, "500 line too long");
+        assert!(client.send("NOOP").await.starts_with("250 "));
+
+        // 510 octets and the CRLF
+        let longest = format!("NOOP {}", "x".repeat(505));
+        assert!(client.send(&longest).await.starts_with("250 "));
+    }
+
+    #[tokio::test]
+    async fn accepts_any_credentials() {
+        let (sink, _mailbox) = start();
+        let mut client = Client::connect(&sink).await;
+        client.send("EHLO client.example").await;
+
+        assert!(client.send("AUTH PLAIN AGJvYgBzZWNyZXQ=").await.starts_with("235 "));
+
+        assert_eq!(client.send("AUTH PLAIN").await, "334");
+        assert!(client.send("AGJvYgBzZWNyZXQ=").await.starts_with("235 "));
+
+        assert_eq!(client.send("AUTH LOGIN").await, "334 VXNlcm5hbWU6");
+        assert_eq!(client.send("Ym9i").await, "334 UGFzc3dvcmQ6");
+        assert!(client.send("c2VjcmV0").await.starts_with("235 "));
+
+        assert_eq!(client.send("AUTH LOGIN Ym9i").await, "334 UGFzc3dvcmQ6");
+        assert!(client.send("c2VjcmV0").await.starts_with("235 "));
+
+        assert!(client.send("AUTH CRAM-MD5").await.starts_with("504 "));
+    }
+
+    #[tokio::test]
+    async fn refuses_starttls() {
+        let (sink, _mailbox) = start();
+        let mut client = Client::connect(&sink).await;
+
+        assert!(!client.send("EHLO client.example").await.contains("STARTTLS"));
+        assert!(client.send("STARTTLS").await.starts_with("502 "));
+        assert!(client.send("NOOP").await.starts_with("250 "));
+    }
+}
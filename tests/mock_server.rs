@@ -0,0 +1,118 @@
+//! The mock server and the client talking to each other over real http
+
+use serde_json::Value;
+use tempmail::{
+    testing::{MockMessage, MockServer},
+    Domain, TempmailError,
+};
+
+const ADDRESS: &str = "bob@1secmail.com";
+
+/// a filename that only survives the trip if it's percent-encoded both ways
+const FILENAME: &str = "q&a=#1 (draft)+na\u{ef}ve%20.txt";
+
+/// what the api answers with for ids that don't exist
+const NOT_FOUND: &str = "Message not found";
+
+fn server() -> MockServer {
+    let server = MockServer::start().unwrap();
+    let mailbox = server.mailbox();
+
+    mailbox.deliver(ADDRESS, MockMessage::new("alice@example.com", "first").text("hello bob"));
+    mailbox.deliver(
+        ADDRESS,
+        MockMessage::new("carol@example.com", "second")
+            .html("<p>see attached</p>")
+            .attachment(FILENAME, "text/plain", "attached"),
+    );
+    mailbox.deliver("eve@1secmail.com", MockMessage::new("alice@example.com", "not bob's"));
+
+    server
+}
+
+async fn get(url: &str, query: &[(&str, &str)]) -> (u16, String) {
+    let res = reqwest::Client::new().get(url).query(query).send().await.unwrap();
+    (res.status().as_u16(), res.text().await.unwrap())
+}
+
+#[tokio::test]
+async fn reads_an_inbox_through_the_client() {
+    let server = server();
+    let inbox = server.inbox("bob", Domain::SecMailCom);
+
+    let raw_msgs = inbox.get_raw_messages().await.unwrap();
+    let listed: Vec<(usize, &str)> = raw_msgs.iter().map(|raw_msg| (raw_msg.id, raw_msg.subject.as_str())).collect();
+    assert_eq!(listed, [(2, "second"), (1, "first")]);
+
+    let first = inbox.read_raw_messsage(&raw_msgs[1]).await.unwrap();
+    assert_eq!((first.from.as_str(), first.text_body.as_str()), ("alice@example.com", "hello bob"));
+
+    let second = inbox.read_raw_messsage(&raw_msgs[0]).await.unwrap();
+    assert_eq!(second.html_body.as_deref(), Some("<p>see attached</p>"));
+    assert_eq!(second.attachments[0].filename, FILENAME);
+
+    assert_eq!(inbox.get_attachment(2, FILENAME).await.unwrap(), b"attached");
+    assert!(matches!(inbox.get_attachment(2, "q&a").await, Err(TempmailError::NotFound)));
+
+    let source = inbox.get_source(1).await.unwrap();
+    assert_eq!(source.header("Subject"), Some("first"));
+    assert_eq!(source.header("To"), Some(ADDRESS));
+
+    assert!(matches!(inbox.get_source(3).await, Err(TempmailError::NotFound)));
+    assert!(matches!(inbox.get_source(9).await, Err(TempmailError::NotFound)));
+}
+
+#[tokio::test]
+async fn serves_the_api_routes() {
+    let server = server();
+    let api = server.base_url();
+    let bob = [("login", "bob"), ("domain", "1secmail.com")];
+
+    let (status, body) = get(&api, &[("action", "getDomainList")]).await;
+    assert_eq!(status, 200);
+    assert!(serde_json::from_str::<Vec<String>>(&body).unwrap().contains(&"1secmail.com".to_string()));
+
+    let (status, body) = get(&api, &[&[("action", "getMessages")], &bob[..]].concat()).await;
+    assert_eq!(status, 200);
+    let messages: Vec<Value> = serde_json::from_str(&body).unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["id"], 2);
+    assert!(messages[0].get("textBody").is_none());
+
+    let (status, body) = get(&api, &[&[("action", "readMessage"), ("id", "1")], &bob[..]].concat()).await;
+    assert_eq!(status, 200);
+    let message: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!((message["subject"].as_str(), message["textBody"].as_str()), (Some("first"), Some("hello bob")));
+
+    // reqwest percent-encodes the filename, and the server has to decode it
+    let download = [&[("action", "download"), ("id", "2"), ("file", FILENAME)], &bob[..]].concat();
+    assert_eq!(get(&api, &download).await, (200, "attached".to_string()));
+
+    let mailbox = api.replace("/api/v1/", "/mailbox/");
+    let (status, body) = get(&mailbox, &[&[("action", "source"), ("id", "2")], &bob[..]].concat()).await;
+    assert_eq!(status, 200);
+    assert!(body.contains("Subject: second\r\n"));
+
+    assert_eq!(get(&api, &[("action", "nope")]).await.0, 400);
+}
+
+#[tokio::test]
+async fn answers_unknown_ids_like_the_api() {
+    let server = server();
+    let api = server.base_url();
+    let mailbox = api.replace("/api/v1/", "/mailbox/");
+    let bob = [("login", "bob"), ("domain", "1secmail.com")];
+
+    // eve's message isn't in bob's inbox
+    let read = [&[("action", "readMessage"), ("id", "3")], &bob[..]].concat();
+    assert_eq!(get(&api, &read).await, (200, NOT_FOUND.to_string()));
+
+    let source = [&[("action", "source"), ("id", "3")], &bob[..]].concat();
+    assert_eq!(get(&mailbox, &source).await, (200, NOT_FOUND.to_string()));
+
+    let download = [&[("action", "download"), ("id", "2"), ("file", "other.txt")], &bob[..]].concat();
+    assert_eq!(get(&api, &download).await, (404, NOT_FOUND.to_string()));
+
+    let inbox = server.inbox("bob", Domain::SecMailCom);
+    assert!(matches!(inbox.provider().read_message("bob", &Domain::SecMailCom, 3).await, Err(TempmailError::NotFound)));
+}